@@ -1,45 +1,719 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    from_binary, to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128,
+    from_binary, to_binary, Addr, Binary, Decimal, Deps, DepsMut, Empty, Env, MessageInfo, Order,
+    QuerierWrapper, Reply, Response, StdResult, Storage, SubMsg, Timestamp, Uint128, WasmMsg,
 };
-use cw2::set_contract_version;
-use cw20::Cw20ReceiveMsg;
+use cw2::{get_contract_version, set_contract_version};
+use cw20::{BalanceResponse, Cw20QueryMsg, Cw20ReceiveMsg, Denom};
+use cw_storage_plus::PrefixBound;
+use cw_utils::Duration;
+use semver::Version;
 
 use crate::error::ContractError;
-use crate::helper::calculate_stake_reward;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg};
-use crate::state::{StakeInfo, State, REWARD_TOTAL, STAKED_TOTAL, STAKE_LIST, STATE};
+use crate::helper::{
+    burn_msg, can_compound, denom_key, get_multiplier, instant_unstake_penalty, is_compoundable,
+    roll_epochs, settle_reward, stake_weight, staked_token, transfer_msg, unbonding_release,
+    unlocked_amount, update_streams, MAX_BPS,
+};
+use crate::legacy;
+use crate::msg::{
+    ExecuteMsg, InstantiateMsg, IsMemberResponse, MembershipQueryMsg, MigrateMsg, QueryMsg,
+    ReceiveMsg, RewardStreamMsg, StakeChangedExecuteMsg, StakeChangedHookMsg,
+};
+use crate::state::{
+    stake_list, ClaimKind, ClaimRecord, ClaimStats, EpochStake, EpochState, LockedPosition,
+    LockupTier, PenaltyConfig, PenaltyDestination, RewardMode, RewardStream, StakeGate, StakeInfo,
+    StakeLimits, State, StreamClaimed, StreamReward, ALLOWLIST, CLAIM_HISTORY, EPOCH,
+    EPOCH_CHECKPOINTS, EPOCH_STAKES, HOOKS, LIQUID_POOL, LOCK_EXPIRIES, RECEIPT_SUPPLY,
+    REWARD_BENEFICIARIES, REWARD_RESERVES, REWARD_STREAMS, STAKED_BALANCES, STAKED_TOTAL,
+    STAKER_COUNT, STATE, TOTAL_STATS, TOTAL_WEIGHT, UNBONDING_TOTAL, USER_STATS,
+};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:cw20-stake";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const EMERGENCY_HOOK_REPLY_ID: u64 = 1;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     let state = State {
         allowed_operators: msg.allowed_operators,
         unstaking_duration: msg.unstaking_duration,
-        apr: msg.apr,
         bdog_ratio: msg.bdog_ratio,
         gdog_ratio: msg.gdog_ratio,
+        dog_burn_address: msg.dog_burn_address,
+        gdog_address: msg.gdog_address,
         token_address: msg.token_address,
+        stake_denom: msg.stake_denom,
         token_source: msg.token_source,
-        reward_token_address: msg.reward_token_address,
+        reward_mode: msg.reward_mode,
+        lockup_tiers: msg.lockup_tiers,
+        instant_unstake_penalty: msg.instant_unstake_penalty,
+        transfers_enabled: msg.transfers_enabled,
+        stake_limits: msg.stake_limits,
+        receipt_token: msg.receipt_token,
+        emergency_mode: false,
+        stake_gate: msg.stake_gate,
     };
+    validate_lockup_tiers(&state.lockup_tiers)?;
+    if let Some(penalty) = &state.instant_unstake_penalty {
+        validate_penalty(penalty)?;
+    }
+    if matches!(state.reward_mode, RewardMode::FixedEmission { duration } if duration.is_zero()) {
+        return Err(ContractError::InvalidEmissionDuration {});
+    }
+    let now = env.block.time.seconds() as u128;
+    if let Some(length) = msg.epoch_length {
+        if length.is_zero() {
+            return Err(ContractError::InvalidEpochLength {});
+        }
+        let epoch = EpochState {
+            genesis: Uint128::new(now),
+            length,
+            epoch: 0,
+            eligible_weight: Uint128::zero(),
+        };
+        EPOCH.save(deps.storage, &epoch)?;
+    }
+    let mut streams = vec![];
+    for stream in msg.reward_streams {
+        streams.push(new_reward_stream(streams.len() as u64, stream, now)?);
+    }
     STATE.save(deps.storage, &state)?;
-    STAKED_TOTAL.save(deps.storage, &Uint128::new(0))?;
-    REWARD_TOTAL.save(deps.storage, &Uint128::new(0))?;
+    REWARD_STREAMS.save(deps.storage, &streams)?;
+    STAKED_TOTAL.save(deps.storage, &Uint128::new(0), env.block.height)?;
+    TOTAL_WEIGHT.save(deps.storage, &Uint128::new(0))?;
+    RECEIPT_SUPPLY.save(deps.storage, &Uint128::new(0))?;
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new().add_attribute("method", "instantiate"))
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let previous = get_contract_version(deps.storage)?;
+    if previous.contract != CONTRACT_NAME {
+        return Err(ContractError::CannotMigrate {
+            previous_contract: previous.contract,
+        });
+    }
+    let previous_version: Version = previous.version.parse()?;
+    let new_version: Version = CONTRACT_VERSION.parse()?;
+    if previous_version > new_version {
+        return Err(ContractError::CannotMigrateVersion {
+            previous_version: previous.version,
+        });
+    }
+    if previous_version < Version::new(0, 2, 0) {
+        migrate_v0_1(deps.branch(), &env)?;
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("from_version", previous.version)
+        .add_attribute("to_version", CONTRACT_VERSION))
+}
+
+// v0.1.0 paid a single CW20 at a string APR. The APR becomes reward stream 0
+// and every position is rewritten with what it was owed settled as pending.
+fn migrate_v0_1(deps: DepsMut, env: &Env) -> Result<(), ContractError> {
+    let legacy_state = legacy::STATE.load(deps.storage)?;
+    let yearly_rate = legacy_state.yearly_rate()?;
+    let state = State {
+        allowed_operators: legacy_state.allowed_operators,
+        unstaking_duration: Duration::Time(legacy_state.unstaking_duration.u128() as u64),
+        bdog_ratio: legacy_state.bdog_ratio,
+        gdog_ratio: legacy_state.gdog_ratio,
+        dog_burn_address: None,
+        gdog_address: None,
+        token_address: legacy_state.token_address,
+        stake_denom: None,
+        token_source: legacy_state.token_source,
+        reward_mode: RewardMode::Apr {},
+        lockup_tiers: vec![],
+        instant_unstake_penalty: None,
+        transfers_enabled: false,
+        stake_limits: StakeLimits::default(),
+        receipt_token: None,
+        emergency_mode: false,
+        stake_gate: StakeGate::default(),
+    };
+    let now = env.block.time.seconds() as u128;
+    let rate = legacy::apr_rate(&legacy_state.apr)?;
+    let reward_token = Denom::Cw20(legacy_state.reward_token_address);
+    let stream = new_reward_stream(
+        0,
+        RewardStreamMsg {
+            token: reward_token.clone(),
+            rate,
+            start_time: None,
+            end_time: None,
+        },
+        now,
+    )?;
+
+    STATE.save(deps.storage, &state)?;
+    REWARD_STREAMS.save(deps.storage, &vec![stream])?;
+    STAKED_TOTAL.save(deps.storage, &Uint128::zero(), env.block.height)?;
+    TOTAL_WEIGHT.save(deps.storage, &Uint128::zero())?;
+    STAKER_COUNT.save(deps.storage, &0)?;
+    UNBONDING_TOTAL.save(deps.storage, &Uint128::zero())?;
+    RECEIPT_SUPPLY.save(deps.storage, &Uint128::zero())?;
+    legacy::REWARD_TOTAL.remove(deps.storage);
+
+    let positions = legacy::STAKE_LIST
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, stake_info)| stake_info))
+        .collect::<StdResult<Vec<_>>>()?;
+    for position in positions {
+        // the raw entry is rewritten through the index so it picks up its index keys
+        legacy::STAKE_LIST.remove(deps.storage, position.owner.clone());
+        let stake_info = position.upgrade(yearly_rate, now);
+        save_stake(deps.storage, env.block.height, None, &stake_info)?;
+    }
+
+    // what the contract holds of the reward token beyond the stakes backs the rewards
+    let balance: BalanceResponse = deps.querier.query_wasm_smart(
+        denom_key(&reward_token),
+        &Cw20QueryMsg::Balance {
+            address: env.contract.address.to_string(),
+        },
+    )?;
+    let mut reserve = balance.balance;
+    if reward_token == staked_token(&state) {
+        let staked = STAKED_TOTAL.load(deps.storage)?;
+        let unbonding = UNBONDING_TOTAL.load(deps.storage)?;
+        reserve = reserve.saturating_sub(staked + unbonding);
+    }
+    REWARD_RESERVES.save(deps.storage, &denom_key(&reward_token), &reserve)?;
+    Ok(())
+}
+
+fn validate_lockup_tiers(tiers: &[LockupTier]) -> Result<(), ContractError> {
+    for (i, tier) in tiers.iter().enumerate() {
+        if tier.boost < Decimal::one() || tier.duration.is_zero() {
+            return Err(ContractError::InvalidLockupTier { id: tier.id });
+        }
+        if tiers[..i].iter().any(|other| other.id == tier.id) {
+            return Err(ContractError::InvalidLockupTier { id: tier.id });
+        }
+    }
+    Ok(())
+}
+
+fn validate_penalty(penalty: &PenaltyConfig) -> Result<(), ContractError> {
+    if penalty.bps > MAX_BPS {
+        return Err(ContractError::InvalidPenalty {});
+    }
+    Ok(())
+}
+
+// Fails unless `addr` passes the stake gate
+fn check_stake_gate(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    state: &State,
+    addr: &Addr,
+) -> Result<(), ContractError> {
+    if !can_stake(storage, querier, state, addr)? {
+        return Err(ContractError::StakeNotAllowed {});
+    }
+    Ok(())
+}
+
+fn can_stake(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    state: &State,
+    addr: &Addr,
+) -> StdResult<bool> {
+    match &state.stake_gate {
+        StakeGate::Open {} => Ok(true),
+        StakeGate::Allowlist {} => Ok(ALLOWLIST.has(storage, addr)),
+        StakeGate::Membership { contract } => {
+            let res: IsMemberResponse = querier.query_wasm_smart(
+                contract,
+                &MembershipQueryMsg::IsMember {
+                    address: addr.to_string(),
+                },
+            )?;
+            Ok(res.is_member)
+        }
+    }
+}
+
+// Checks a stake of `amount` that brings the staker to `address_stake`
+// and the pool to `total_stake`
+fn check_stake_limits(
+    limits: &StakeLimits,
+    amount: Uint128,
+    address_stake: Uint128,
+    total_stake: Uint128,
+) -> Result<(), ContractError> {
+    if let Some(min) = limits.min_stake {
+        if amount < min {
+            return Err(ContractError::BelowMinStake { min });
+        }
+    }
+    check_stake_caps(limits, address_stake, total_stake)
+}
+
+// The per address and total caps alone, for stake that grows without a new deposit
+fn check_stake_caps(
+    limits: &StakeLimits,
+    address_stake: Uint128,
+    total_stake: Uint128,
+) -> Result<(), ContractError> {
+    if let Some(max) = limits.max_stake_per_address {
+        if address_stake > max {
+            return Err(ContractError::AddressStakeLimitExceeded { max });
+        }
+    }
+    if let Some(max) = limits.max_total_stake {
+        if total_stake > max {
+            return Err(ContractError::TotalStakeLimitExceeded { max });
+        }
+    }
+    Ok(())
+}
+
+fn new_reward_stream(
+    id: u64,
+    msg: RewardStreamMsg,
+    now: u128,
+) -> Result<RewardStream, ContractError> {
+    let start_time = msg.start_time.unwrap_or_else(|| Uint128::new(now));
+    if let Some(end_time) = msg.end_time {
+        if end_time <= start_time {
+            return Err(ContractError::InvalidRewardStream {
+                msg: "end time must be after start time".to_string(),
+            });
+        }
+    }
+    Ok(RewardStream {
+        id,
+        token: msg.token,
+        rate: msg.rate,
+        start_time,
+        end_time: msg.end_time,
+        reward_per_token: Default::default(),
+        last_update_time: start_time,
+    })
+}
+
+// Position of an address that never staked before
+fn new_stake_info(owner: Addr, time: Timestamp) -> StakeInfo {
+    StakeInfo {
+        owner,
+        stake_amount: Uint128::zero(),
+        unstaking_amount: Uint128::zero(),
+        rewards: vec![],
+        stake_start_time: time,
+        reward_start_time: time,
+        unstaking_start_time: Timestamp::from_seconds(0),
+        unstaking_start_height: 0,
+        unstaking_process: false,
+        auto_compound: false,
+        locked_positions: vec![],
+    }
+}
+
+// Reward streams accrued up to now, in epoch mode up to the start of the current epoch
+struct Accrued {
+    streams: Vec<RewardStream>,
+    epoch: Option<EpochState>,
+    // reward per token when each epoch that started since the last accrual started
+    checkpoints: Vec<(u64, Vec<Decimal>)>,
+}
+
+fn accrued_streams(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    state: &State,
+    now: u128,
+) -> StdResult<Accrued> {
+    let mut streams = REWARD_STREAMS.load(storage)?;
+    let total_weight = TOTAL_WEIGHT.load(storage)?;
+    let multiplier = get_multiplier(querier, state);
+    let mut epoch = EPOCH.may_load(storage)?;
+    let checkpoints = match epoch.as_mut() {
+        Some(epoch) => roll_epochs(
+            epoch,
+            &mut streams,
+            &state.reward_mode,
+            multiplier,
+            total_weight,
+            now,
+        )?,
+        None => {
+            update_streams(
+                &mut streams,
+                &state.reward_mode,
+                multiplier,
+                total_weight,
+                now,
+            )?;
+            vec![]
+        }
+    };
+    Ok(Accrued {
+        streams,
+        epoch,
+        checkpoints,
+    })
+}
+
+// Accrues every reward stream up to now and stores the result, releasing the
+// boosts that ran out on the way. Stakes are loaded after it as it may save them.
+fn accrue_streams(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    state: &State,
+    now: u128,
+) -> StdResult<Vec<RewardStream>> {
+    release_expired_locks(storage, querier, state, now)?;
+    accrue_streams_until(storage, querier, state, now)
+}
+
+// Settles every stake with a lock that ran out by `now` at its unlock time, so the
+// boost leaves TOTAL_WEIGHT when it stops being paid instead of diluting the others
+// until the owner comes back
+fn release_expired_locks(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    state: &State,
+    now: u128,
+) -> StdResult<()> {
+    let expired = LOCK_EXPIRIES
+        .prefix_range(
+            storage,
+            None,
+            Some(PrefixBound::inclusive(now as u64)),
+            Order::Ascending,
+        )
+        .collect::<StdResult<Vec<_>>>()?;
+    for ((unlock_time, owner), _) in expired {
+        LOCK_EXPIRIES.remove(storage, (unlock_time, &owner));
+        let unlock_time = unlock_time as u128;
+        let streams = accrue_streams_until(storage, querier, state, unlock_time)?;
+        // stakes withdrawn or settled since then have no expired lock left
+        let mut stake_info = match stake_list().may_load(storage, owner.clone())? {
+            Some(stake_info) if (stake_info.reward_start_time.seconds() as u128) < unlock_time => {
+                stake_info
+            }
+            _ => continue,
+        };
+        let old_weight = stake_weight(&stake_info);
+        settle(storage, &streams, &mut stake_info, unlock_time)?;
+        save_weight(storage, &owner, old_weight, stake_weight(&stake_info))?;
+        stake_list().save(storage, owner, &stake_info)?;
+    }
+    Ok(())
+}
+
+fn accrue_streams_until(
+    storage: &mut dyn Storage,
+    querier: &QuerierWrapper,
+    state: &State,
+    now: u128,
+) -> StdResult<Vec<RewardStream>> {
+    let accrued = accrued_streams(storage, querier, state, now)?;
+    if let Some(epoch) = &accrued.epoch {
+        EPOCH.save(storage, epoch)?;
+    }
+    for (epoch, checkpoint) in &accrued.checkpoints {
+        EPOCH_CHECKPOINTS.save(storage, *epoch, checkpoint)?;
+    }
+    REWARD_STREAMS.save(storage, &accrued.streams)?;
+    Ok(accrued.streams)
+}
+
+// Time the streams are accrued to, the start of the current epoch in epoch mode
+fn accrued_until(storage: &dyn Storage, now: u128) -> StdResult<u128> {
+    Ok(match EPOCH.may_load(storage)? {
+        Some(epoch) => epoch.epoch_start(epoch.epoch).u128(),
+        None => now,
+    })
+}
+
+// Pays the epoch a stake last changed in at the weight it held through that epoch.
+// Later epochs are paid at its full weight by settle_reward, which has to follow.
+fn settle_epoch(
+    storage: &dyn Storage,
+    checkpoints: &[(u64, Vec<Decimal>)],
+    stake_info: &mut StakeInfo,
+) -> StdResult<()> {
+    let stake = match EPOCH_STAKES.may_load(storage, &stake_info.owner)? {
+        Some(stake) => stake,
+        None => return Ok(()),
+    };
+    let next = stake.epoch + 1;
+    let checkpoint = match checkpoints.iter().find(|(epoch, _)| *epoch == next) {
+        Some((_, checkpoint)) => Some(checkpoint.clone()),
+        None => EPOCH_CHECKPOINTS.may_load(storage, next)?,
+    };
+    // the epoch is still running
+    let checkpoint = match checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(()),
+    };
+    for (stream_id, reward_per_token) in checkpoint.into_iter().enumerate() {
+        let stream_id = stream_id as u64;
+        let index = match stake_info
+            .rewards
+            .iter()
+            .position(|r| r.stream_id == stream_id)
+        {
+            Some(index) => index,
+            None => {
+                stake_info.rewards.push(StreamReward {
+                    stream_id,
+                    pending: Uint128::zero(),
+                    reward_per_token_paid: Decimal::zero(),
+                });
+                stake_info.rewards.len() - 1
+            }
+        };
+        let reward = &mut stake_info.rewards[index];
+        reward.pending += stake.eligible_weight * (reward_per_token - reward.reward_per_token_paid);
+        reward.reward_per_token_paid = reward_per_token;
+    }
+    Ok(())
+}
+
+// Settles a stake against streams returned by accrue_streams
+fn settle(
+    storage: &dyn Storage,
+    streams: &[RewardStream],
+    stake_info: &mut StakeInfo,
+    now: u128,
+) -> StdResult<()> {
+    settle_epoch(storage, &[], stake_info)?;
+    settle_reward(streams, stake_info, now);
+    Ok(())
+}
+
+// Saves a stake and moves STAKED_TOTAL and TOTAL_WEIGHT by what changed since `old`.
+// Staked balances are snapshotted at `height` for voting power queries.
+// Returns the hook messages to send when the staked amount changed.
+fn save_stake(
+    storage: &mut dyn Storage,
+    height: u64,
+    old: Option<&StakeInfo>,
+    stake_info: &StakeInfo,
+) -> StdResult<Vec<SubMsg>> {
+    let (old_amount, old_weight) = old
+        .map(|old| (old.stake_amount, stake_weight(old)))
+        .unwrap_or_default();
+    let old_unbonding = old.map(|old| old.unstaking_amount).unwrap_or_default();
+    if stake_info.unstaking_amount != old_unbonding {
+        let unbonding = UNBONDING_TOTAL.may_load(storage)?.unwrap_or_default();
+        UNBONDING_TOTAL.save(
+            storage,
+            &(unbonding + stake_info.unstaking_amount - old_unbonding),
+        )?;
+    }
+    if old_amount.is_zero() != stake_info.stake_amount.is_zero() {
+        let stakers = STAKER_COUNT.may_load(storage)?.unwrap_or_default();
+        let stakers = if old_amount.is_zero() {
+            stakers + 1
+        } else {
+            stakers - 1
+        };
+        STAKER_COUNT.save(storage, &stakers)?;
+    }
+    let total = STAKED_TOTAL.load(storage)?;
+    let mut hooks = vec![];
+    if stake_info.stake_amount != old_amount {
+        hooks = stake_changed_hooks(
+            storage,
+            StakeChangedHookMsg {
+                addr: stake_info.owner.clone(),
+                old: old_amount,
+                new: stake_info.stake_amount,
+            },
+        )?;
+        STAKED_TOTAL.save(
+            storage,
+            &(total + stake_info.stake_amount - old_amount),
+            height,
+        )?;
+        STAKED_BALANCES.save(storage, &stake_info.owner, &stake_info.stake_amount, height)?;
+    }
+    save_weight(
+        storage,
+        &stake_info.owner,
+        old_weight,
+        stake_weight(stake_info),
+    )?;
+    stake_list().save(storage, stake_info.owner.clone(), stake_info)?;
+    Ok(hooks)
+}
+
+// Saves the liquid pool position, which only moves TOTAL_WEIGHT
+fn save_pool(
+    storage: &mut dyn Storage,
+    old: Option<&StakeInfo>,
+    pool: &StakeInfo,
+) -> StdResult<()> {
+    let old_weight = old.map(stake_weight).unwrap_or_default();
+    save_weight(storage, &pool.owner, old_weight, stake_weight(pool))?;
+    LIQUID_POOL.save(storage, pool)
+}
+
+// Moves TOTAL_WEIGHT by a stake's weight change and tracks the weight it keeps
+// through the current epoch
+fn save_weight(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    old_weight: Uint128,
+    weight: Uint128,
+) -> StdResult<()> {
+    // an unchanged weight leaves the epoch record alone, it may belong to an
+    // epoch this stake was not settled for yet
+    if weight == old_weight {
+        return Ok(());
+    }
+    let total_weight = TOTAL_WEIGHT.load(storage)?;
+    TOTAL_WEIGHT.save(storage, &(total_weight + weight - old_weight))?;
+    // only the weight kept through the whole epoch earns it, added stake starts
+    // earning with the next epoch
+    if let Some(mut epoch) = EPOCH.may_load(storage)? {
+        let held = match EPOCH_STAKES.may_load(storage, owner)? {
+            Some(stake) if stake.epoch == epoch.epoch => stake.eligible_weight,
+            _ => old_weight,
+        };
+        let eligible_weight = held.min(weight);
+        epoch.eligible_weight -= held - eligible_weight;
+        EPOCH.save(storage, &epoch)?;
+        EPOCH_STAKES.save(
+            storage,
+            owner,
+            &EpochStake {
+                epoch: epoch.epoch,
+                eligible_weight,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+// Everything staked, the liquid pool included
+fn total_staked(storage: &dyn Storage) -> StdResult<Uint128> {
+    let pool = LIQUID_POOL.may_load(storage)?;
+    Ok(STAKED_TOTAL.load(storage)? + pool.map(|pool| pool.stake_amount).unwrap_or_default())
+}
+
+// Adds a payout to the claimer's history and to the user and overall totals
+fn record_claim(
+    storage: &mut dyn Storage,
+    env: &Env,
+    addr: &Addr,
+    kind: ClaimKind,
+    amount: Uint128,
+) -> StdResult<()> {
+    let mut user_stats = USER_STATS.may_load(storage, addr)?.unwrap_or_default();
+    let mut total_stats = TOTAL_STATS.may_load(storage)?.unwrap_or_default();
+    for stats in [&mut user_stats, &mut total_stats] {
+        add_claim(stats, &kind, amount);
+    }
+    let record = ClaimRecord {
+        kind,
+        amount,
+        height: env.block.height,
+        time: env.block.time,
+    };
+    CLAIM_HISTORY.save(storage, (addr, user_stats.claims - 1), &record)?;
+    USER_STATS.save(storage, addr, &user_stats)?;
+    TOTAL_STATS.save(storage, &total_stats)
+}
+
+fn add_claim(stats: &mut ClaimStats, kind: &ClaimKind, amount: Uint128) {
+    stats.claims += 1;
+    match kind {
+        ClaimKind::Reward { stream_id, .. } | ClaimKind::Compounded { stream_id, .. } => {
+            match stats
+                .rewards_claimed
+                .iter_mut()
+                .find(|claimed| claimed.stream_id == *stream_id)
+            {
+                Some(claimed) => claimed.amount += amount,
+                None => stats.rewards_claimed.push(StreamClaimed {
+                    stream_id: *stream_id,
+                    amount,
+                }),
+            }
+        }
+        ClaimKind::Unstaked {} => stats.unstaked_claimed += amount,
+    }
+}
+
+fn stake_changed_hooks(storage: &dyn Storage, msg: StakeChangedHookMsg) -> StdResult<Vec<SubMsg>> {
+    let msg = to_binary(&StakeChangedExecuteMsg::StakeChangeHook(msg))?;
+    HOOKS
+        .may_load(storage)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|hook| {
+            Ok(SubMsg::new(WasmMsg::Execute {
+                contract_addr: hook.to_string(),
+                msg: msg.clone(),
+                funds: vec![],
+            }))
+        })
+        .collect()
+}
+
+// Claims notify the hooks even when the staked amount stays the same
+fn claim_hooks(
+    storage: &dyn Storage,
+    old: &StakeInfo,
+    stake_info: &StakeInfo,
+    hooks: Vec<SubMsg>,
+) -> StdResult<Vec<SubMsg>> {
+    if !hooks.is_empty() {
+        return Ok(hooks);
+    }
+    stake_changed_hooks(
+        storage,
+        StakeChangedHookMsg {
+            addr: stake_info.owner.clone(),
+            old: old.stake_amount,
+            new: stake_info.stake_amount,
+        },
+    )
+}
+
+// Hooks of an emergency exit report back on failure, where `reply` drops the
+// error so a failing hook can't block the exit
+fn emergency_hooks(hooks: Vec<SubMsg>) -> Vec<SubMsg> {
+    hooks
+        .into_iter()
+        .map(|hook| SubMsg::reply_on_error(hook.msg, EMERGENCY_HOOK_REPLY_ID))
+        .collect()
+}
+
+// Takes up to `amount` out of the token's reward reserve, returns what could be covered
+fn take_from_reserve(
+    storage: &mut dyn Storage,
+    token: &Denom,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let key = denom_key(token);
+    let reserve = REWARD_RESERVES.may_load(storage, &key)?.unwrap_or_default();
+    let covered = reserve.min(amount);
+    REWARD_RESERVES.save(storage, &key, &(reserve - covered))?;
+    Ok(covered)
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -48,18 +722,45 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
+        ExecuteMsg::Stake {} => execute::stake_native(deps, env, info, None),
+        ExecuteMsg::StakeLocked { tier_id } => {
+            execute::stake_native(deps, env, info, Some(tier_id))
+        }
+        ExecuteMsg::StakeLiquid {} => {
+            let state = STATE.load(deps.storage)?;
+            let denom = state.stake_denom.ok_or(ContractError::InvalidToken {})?;
+            let amount = cw_utils::must_pay(&info, &denom)?;
+            execute::stake_liquid(deps, env, info.sender, amount)
+        }
         ExecuteMsg::StartUnstake { amount } => execute::start_unstake(deps, env, info, amount),
-        ExecuteMsg::ClaimReward { amount } => execute::claim_reward(deps, env, info, amount),
+        ExecuteMsg::EmergencyWithdraw {} => execute::emergency_withdraw(deps, env, info),
+        ExecuteMsg::ClaimReward { amount, recipient } => {
+            execute::claim_reward(deps, env, info, amount, recipient)
+        }
         ExecuteMsg::ClaimUnstaked { amount } => execute::claim_unstaked(deps, info, env, amount),
+        ExecuteMsg::CancelUnstake { amount } => execute::cancel_unstake(deps, env, info, amount),
+        ExecuteMsg::InstantUnstake { amount } => execute::instant_unstake(deps, env, info, amount),
+        ExecuteMsg::TransferStake { recipient, amount } => {
+            execute::transfer_stake(deps, env, info, recipient, amount)
+        }
+        ExecuteMsg::Compound {} => execute::compound(deps, env, info),
+        ExecuteMsg::SetAutoCompound { enabled } => execute::set_auto_compound(deps, info, enabled),
         ExecuteMsg::EditState {
             allowed_operators,
             token_address,
             unstaking_duration,
-            apr,
             bdog_ratio,
             gdog_ratio,
+            dog_burn_address,
+            gdog_address,
             token_source,
-            reward_token_address,
+            lockup_tiers,
+            instant_unstake_penalty,
+            transfers_enabled,
+            stake_limits,
+            receipt_token,
+            emergency_mode,
+            stake_gate,
         } => execute::edit_state(
             deps,
             env,
@@ -67,12 +768,65 @@ pub fn execute(
             allowed_operators,
             token_address,
             unstaking_duration,
-            apr,
             bdog_ratio,
             gdog_ratio,
+            dog_burn_address,
+            gdog_address,
             token_source,
-            reward_token_address,
+            lockup_tiers,
+            instant_unstake_penalty,
+            transfers_enabled,
+            stake_limits,
+            receipt_token,
+            emergency_mode,
+            stake_gate,
+        ),
+        ExecuteMsg::SetRewardBeneficiary { addr } => {
+            execute::set_reward_beneficiary(deps, info, addr)
+        }
+        ExecuteMsg::UpdateAllowlist { add, remove } => {
+            execute::update_allowlist(deps, info, add, remove)
+        }
+        ExecuteMsg::AddRewardStream {
+            token,
+            rate,
+            start_time,
+            end_time,
+        } => execute::add_reward_stream(
+            deps,
+            env,
+            info,
+            RewardStreamMsg {
+                token,
+                rate,
+                start_time,
+                end_time,
+            },
         ),
+        ExecuteMsg::UpdateRewardStream { id, rate, end_time } => {
+            execute::update_reward_stream(deps, env, info, id, rate, end_time)
+        }
+        ExecuteMsg::AddHook { addr } => execute::add_hook(deps, info, addr),
+        ExecuteMsg::RemoveHook { addr } => execute::remove_hook(deps, info, addr),
+        ExecuteMsg::FundRewards {} => {
+            let funds = info
+                .funds
+                .iter()
+                .map(|coin| (Denom::Native(coin.denom.clone()), coin.amount))
+                .collect();
+            execute::fund_rewards(deps, info.sender, funds)
+        }
+        ExecuteMsg::NotifyRewardAmount { stream_id } => {
+            let coin = cw_utils::one_coin(&info)?;
+            execute::notify_reward_amount(
+                deps,
+                env,
+                info.sender,
+                stream_id,
+                Denom::Native(coin.denom),
+                coin.amount,
+            )
+        }
         ExecuteMsg::Receive(msg) => execute_receive(deps, env, info, msg),
     }
 }
@@ -84,71 +838,136 @@ pub fn execute_receive(
     wrapper: Cw20ReceiveMsg,
 ) -> Result<Response, ContractError> {
     let state = STATE.load(deps.storage)?;
-    if info.sender != state.token_address {
-        return Err(ContractError::InvalidToken {});
-    }
     let msg: ReceiveMsg = from_binary(&wrapper.msg)?;
     let sender = deps.api.addr_validate(&wrapper.sender)?;
     match msg {
-        ReceiveMsg::Stake {} => execute::stake(deps, env, sender, wrapper.amount),
+        ReceiveMsg::Stake {} => {
+            if Denom::Cw20(info.sender.clone()) != staked_token(&state) {
+                return Err(ContractError::InvalidToken {});
+            }
+            execute::stake(deps, env, sender.clone(), sender, wrapper.amount, None)
+        }
+        ReceiveMsg::StakeLocked { tier_id } => {
+            if Denom::Cw20(info.sender.clone()) != staked_token(&state) {
+                return Err(ContractError::InvalidToken {});
+            }
+            execute::stake(
+                deps,
+                env,
+                sender.clone(),
+                sender,
+                wrapper.amount,
+                Some(tier_id),
+            )
+        }
+        ReceiveMsg::StakeFor { recipient } => {
+            if Denom::Cw20(info.sender.clone()) != staked_token(&state) {
+                return Err(ContractError::InvalidToken {});
+            }
+            let recipient = deps.api.addr_validate(&recipient)?;
+            execute::stake(deps, env, sender, recipient, wrapper.amount, None)
+        }
+        ReceiveMsg::StakeLiquid {} => {
+            if Denom::Cw20(info.sender.clone()) != staked_token(&state) {
+                return Err(ContractError::InvalidToken {});
+            }
+            execute::stake_liquid(deps, env, sender, wrapper.amount)
+        }
+        ReceiveMsg::UnstakeLiquid {} => {
+            if state.receipt_token != Some(info.sender) {
+                return Err(ContractError::InvalidToken {});
+            }
+            execute::unstake_liquid(deps, env, sender, wrapper.amount)
+        }
+        ReceiveMsg::FundRewards {} => execute::fund_rewards(
+            deps,
+            sender,
+            vec![(Denom::Cw20(info.sender), wrapper.amount)],
+        ),
+        ReceiveMsg::NotifyRewardAmount { stream_id } => execute::notify_reward_amount(
+            deps,
+            env,
+            sender,
+            stream_id,
+            Denom::Cw20(info.sender),
+            wrapper.amount,
+        ),
     }
 }
 
 pub mod execute {
     use super::*;
-    use cosmwasm_std::{Addr, Order, SubMsg};
-    use cw20::{Cw20Contract, Cw20ExecuteMsg};
+    use cw20::Cw20ExecuteMsg;
+
+    // Stakes the funds attached in the configured native denom
+    pub fn stake_native(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        tier_id: Option<u64>,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let denom = state.stake_denom.ok_or(ContractError::InvalidToken {})?;
+        let amount = cw_utils::must_pay(&info, &denom)?;
+        stake(deps, env, info.sender.clone(), info.sender, amount, tier_id)
+    }
 
+    // Stakes `amount` sent by `funder` to the position of `sender`
     pub fn stake(
         deps: DepsMut,
         env: Env,
+        funder: Addr,
         sender: Addr,
         amount: Uint128,
+        tier_id: Option<u64>,
     ) -> Result<Response, ContractError> {
         let state = STATE.load(deps.storage)?;
+        if state.emergency_mode {
+            return Err(ContractError::EmergencyMode {});
+        }
+        check_stake_gate(deps.storage, &deps.querier, &state, &sender)?;
         let now = env.block.time.seconds() as u128;
-        let time = Uint128::new(now);
+        let time = env.block.time;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
 
-        let stake_info = STAKE_LIST.load(deps.storage, sender.clone());
-        let stake;
-        let new_apr =
-            state.gdog_ratio / state.bdog_ratio * Uint128::new(state.apr.parse::<u128>().unwrap());
-        if stake_info.is_err() {
+        let old = stake_list().may_load(deps.storage, sender.clone())?;
+        let mut stake = old
+            .clone()
             //No previous stake data exists
-            stake = StakeInfo {
-                owner: sender.clone(),
-                stake_amount: amount,
-                apr: new_apr.to_string(),
-                unstaking_amount: Uint128::zero(),
-                reward_amount: Uint128::zero(),
-                stake_start_time: time,
-                reward_start_time: time,
-                unstaking_start_time: Uint128::zero(),
-                unstaking_process: false,
-                reward_end_time: Uint128::zero(),
-                unstake_end_time: Uint128::zero(),
-            };
-        } else {
-            let stake_data = stake_info?;
-            let curr_reward = calculate_stake_reward(new_apr, stake_data.clone(), now);
-            stake = StakeInfo {
-                owner: sender.clone(),
-                stake_amount: amount + stake_data.stake_amount,
-                apr: new_apr.to_string(),
-                stake_start_time: time,
-                reward_start_time: time,
-                unstaking_start_time: Uint128::zero(),
-                unstaking_process: false,
-                reward_end_time: Uint128::zero(),
-                unstaking_amount: stake_data.unstaking_amount,
-                reward_amount: curr_reward + stake_data.reward_amount,
-                unstake_end_time: stake_data.unstake_end_time,
-            };
+            .unwrap_or_else(|| new_stake_info(sender.clone(), time));
+        settle(deps.storage, &streams, &mut stake, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut stake)?;
+        stake.stake_amount += amount;
+        stake.stake_start_time = time;
+
+        if let Some(tier_id) = tier_id {
+            let tier = state
+                .lockup_tiers
+                .iter()
+                .find(|tier| tier.id == tier_id)
+                .ok_or(ContractError::LockupTierNotFound { id: tier_id })?;
+            // every lock keeps its own term, adding to a tier does not relock earlier deposits
+            let unlock_time = time.plus_seconds(tier.duration.u128() as u64);
+            stake.locked_positions.push(LockedPosition {
+                tier_id,
+                amount,
+                boost: tier.boost,
+                unlock_time,
+            });
+            LOCK_EXPIRIES.save(deps.storage, (unlock_time.seconds(), &sender), &Empty {})?;
         }
-        let total = STAKED_TOTAL.load(deps.storage)?;
-        STAKE_LIST.save(deps.storage, sender, &stake)?;
-        STAKED_TOTAL.save(deps.storage, &(total + amount))?;
-        Ok(Response::new())
+
+        let old_amount = old.as_ref().map(|old| old.stake_amount).unwrap_or_default();
+        let total_stake = total_staked(deps.storage)? + stake.stake_amount - old_amount;
+        check_stake_limits(&state.stake_limits, amount, stake.stake_amount, total_stake)?;
+
+        let hooks = save_stake(deps.storage, env.block.height, old.as_ref(), &stake)?;
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "stake")
+            .add_attribute("funder", funder)
+            .add_attribute("beneficiary", sender)
+            .add_attribute("amount", amount))
     }
 
     pub fn start_unstake(
@@ -157,36 +976,42 @@ pub mod execute {
         info: MessageInfo,
         amount: Uint128,
     ) -> Result<Response, ContractError> {
-        let mut stake_info = STAKE_LIST.load(deps.storage, info.sender.clone())?;
         let state = STATE.load(deps.storage)?;
-        if stake_info.stake_amount < amount {
-            return Err(ContractError::MoreThanStakeAmount {});
-        }
-        if stake_info.owner != info.sender.to_string() {
+        let now = env.block.time.seconds() as u128;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+        if stake_info.owner != info.sender {
             return Err(ContractError::Unauthorized {
                 msg: "only owner can unstake".to_string(),
             });
         }
-        let now = env.block.time.seconds() as u128;
-        let time = Uint128::new(now);
-        let apr =
-            state.gdog_ratio / state.bdog_ratio * Uint128::new(state.apr.parse::<u128>().unwrap());
-        let curr_reward = calculate_stake_reward(apr, stake_info.clone(), now);
+        let time = env.block.time;
+        let old = stake_info.clone();
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut stake_info)?;
 
+        if stake_info.stake_amount < amount {
+            return Err(ContractError::MoreThanStakeAmount {});
+        }
+        let unlocked = unlocked_amount(&stake_info);
+        if unlocked < amount {
+            return Err(ContractError::MoreThanUnlockedAmount {
+                available: unlocked,
+            });
+        }
         let remaining_stake_balance = stake_info.stake_amount - amount;
         stake_info.unstaking_process = true;
         stake_info.unstaking_start_time = time;
-        stake_info.reward_end_time = time;
-        stake_info.unstaking_amount = stake_info.unstaking_amount + amount;
-        stake_info.unstake_end_time = time + state.unstaking_duration;
+        stake_info.unstaking_start_height = env.block.height;
+        stake_info.unstaking_amount += amount;
         stake_info.stake_amount = remaining_stake_balance;
+        let release = unbonding_release(&state, &stake_info);
 
-        let total = STAKED_TOTAL.load(deps.storage)?;
-        STAKED_TOTAL.save(deps.storage, &(total - amount))?;
-
-        stake_info.reward_amount = stake_info.reward_amount + curr_reward;
-        STAKE_LIST.save(deps.storage, info.sender, &stake_info)?;
-        Ok(Response::new().add_attribute("action", "start_unstake"))
+        let hooks = save_stake(deps.storage, env.block.height, Some(&old), &stake_info)?;
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "start_unstake")
+            .add_attribute("release", release.to_string()))
     }
 
     pub fn claim_unstaked(
@@ -195,18 +1020,20 @@ pub mod execute {
         env: Env,
         amount: Uint128,
     ) -> Result<Response, ContractError> {
-        let mut stake_info = STAKE_LIST.load(deps.storage, info.sender.clone())?;
         let state = STATE.load(deps.storage)?;
+        let now = env.block.time.seconds() as u128;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
 
         if !stake_info.unstaking_process {
             return Err(ContractError::UnstakingProcessIsNotStarted {});
         }
-        let now = env.block.time.seconds() as u128;
-        let time = Uint128::new(now);
-        if time - stake_info.unstaking_start_time < state.unstaking_duration
-            || stake_info.unstaking_start_time == Uint128::zero()
-        {
-            return Err(ContractError::MinUnstakingTimeRequired {});
+        let old = stake_info.clone();
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut stake_info)?;
+        let release = unbonding_release(&state, &stake_info);
+        if !release.is_expired(&env.block) {
+            return Err(ContractError::MinUnstakingTimeRequired { release });
         }
 
         if stake_info.unstaking_amount < amount {
@@ -216,144 +1043,1017 @@ pub mod execute {
             });
         }
 
-        stake_info.unstaking_amount = stake_info.unstaking_amount - amount;
+        stake_info.unstaking_amount -= amount;
         if stake_info.unstaking_amount == Uint128::zero() {
-            stake_info.unstaking_start_time = Uint128::zero();
+            stake_info.unstaking_start_time = Timestamp::from_seconds(0);
+            stake_info.unstaking_start_height = 0;
             stake_info.unstaking_process = false;
             if stake_info.stake_amount == Uint128::zero() {
-                stake_info.stake_start_time = Uint128::zero();
-                stake_info.reward_start_time = Uint128::zero();
+                stake_info.stake_start_time = Timestamp::from_seconds(0);
+                stake_info.reward_start_time = Timestamp::from_seconds(0);
             }
         }
 
-        let cw20_execute_send = Cw20ExecuteMsg::Transfer {
-            recipient: info.sender.to_string(),
+        let reward_send_msg = transfer_msg(&staked_token(&state), &info.sender, amount)?;
+        record_claim(
+            deps.storage,
+            &env,
+            &info.sender,
+            ClaimKind::Unstaked {},
             amount,
-        };
-        let reward_send_msg = Cw20Contract(state.token_address)
-            .call(cw20_execute_send)
-            .map_err(ContractError::Std)?;
+        )?;
 
-        STAKE_LIST.save(deps.storage, info.sender.clone(), &stake_info)?;
+        let hooks = save_stake(deps.storage, env.block.height, Some(&old), &stake_info)?;
+        let hooks = claim_hooks(deps.storage, &old, &stake_info, hooks)?;
 
         Ok(Response::new()
             .add_submessages(vec![SubMsg::new(reward_send_msg)])
+            .add_submessages(hooks)
             .add_attribute("action", "claim_unstaked"))
     }
 
-    pub fn claim_reward(
+    // Unstakes right away instead of waiting unstaking_duration, a part of the
+    // amount is kept as penalty and handled as the penalty config says
+    pub fn instant_unstake(
         deps: DepsMut,
         env: Env,
         info: MessageInfo,
-        _amount: Uint128,
+        amount: Uint128,
     ) -> Result<Response, ContractError> {
-        let mut stake_info = STAKE_LIST.load(deps.storage, info.sender.clone())?;
         let state = STATE.load(deps.storage)?;
-
         let now = env.block.time.seconds() as u128;
+        let mut streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+        let config = state
+            .instant_unstake_penalty
+            .clone()
+            .ok_or(ContractError::InstantUnstakeDisabled {})?;
 
-        let apr =
-            state.gdog_ratio / state.bdog_ratio * Uint128::new(state.apr.parse::<u128>().unwrap());
-        let reward_with_decimals = calculate_stake_reward(apr, stake_info.clone(), now);
-        let curr_reward = reward_with_decimals / (Uint128::new(100000));
-        let total_reward = stake_info.reward_amount + curr_reward;
+        let old = stake_info.clone();
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut stake_info)?;
 
-        let cw20_execute_msg_fp = Cw20ExecuteMsg::Transfer {
-            recipient: info.sender.to_string(),
-            amount: total_reward,
-        };
-        let fee_payout_msg = Cw20Contract(state.reward_token_address)
-            .call(cw20_execute_msg_fp)
-            .map_err(ContractError::Std)?;
+        if stake_info.stake_amount < amount {
+            return Err(ContractError::MoreThanStakeAmount {});
+        }
+        let unlocked = unlocked_amount(&stake_info);
+        if unlocked < amount {
+            return Err(ContractError::MoreThanUnlockedAmount {
+                available: unlocked,
+            });
+        }
+        stake_info.stake_amount -= amount;
+        let hooks = save_stake(deps.storage, env.block.height, Some(&old), &stake_info)?;
+
+        let (penalty, payout) = instant_unstake_penalty(amount, config.bps);
+        let token = staked_token(&state);
+        let mut messages = hooks;
+        if !payout.is_zero() {
+            messages.push(SubMsg::new(transfer_msg(&token, &info.sender, payout)?));
+            record_claim(
+                deps.storage,
+                &env,
+                &info.sender,
+                ClaimKind::Unstaked {},
+                payout,
+            )?;
+        }
+        if !penalty.is_zero() {
+            match config.destination {
+                PenaltyDestination::Burn {} => {
+                    messages.push(SubMsg::new(burn_msg(&token, penalty)?));
+                }
+                PenaltyDestination::Treasury { address } => {
+                    messages.push(SubMsg::new(transfer_msg(&token, &address, penalty)?));
+                }
+                // paid out as a reward on the first stream in the staked token
+                PenaltyDestination::Redistribute {} => {
+                    let stream = streams
+                        .iter_mut()
+                        .find(|stream| is_compoundable(&state, stream))
+                        .ok_or(ContractError::CompoundNotSupported {})?;
+                    let total_weight = TOTAL_WEIGHT.load(deps.storage)?;
+                    if !total_weight.is_zero() {
+                        stream.reward_per_token += Decimal::from_ratio(penalty, total_weight);
+                    }
+                    let key = denom_key(&stream.token);
+                    let reserve = REWARD_RESERVES
+                        .may_load(deps.storage, &key)?
+                        .unwrap_or_default();
+                    REWARD_RESERVES.save(deps.storage, &key, &(reserve + penalty))?;
+                    REWARD_STREAMS.save(deps.storage, &streams)?;
+                }
+            }
+        }
 
-        stake_info.reward_amount = Uint128::zero();
-        stake_info.stake_start_time = Uint128::new(now);
-        STAKE_LIST.save(deps.storage, info.sender, &stake_info)?;
         Ok(Response::new()
-            .add_submessages(vec![SubMsg::new(fee_payout_msg)])
-            .add_attribute("method", "distribute_reward"))
+            .add_submessages(messages)
+            .add_attribute("action", "instant_unstake")
+            .add_attribute("amount", amount)
+            .add_attribute("penalty", penalty)
+            .add_attribute("payout", payout))
     }
 
-    pub fn edit_state(
+    // Pays out the whole principal without touching reward accounting,
+    // so it keeps working when the reward state is broken
+    pub fn emergency_withdraw(
         deps: DepsMut,
         env: Env,
         info: MessageInfo,
-        allowed_operators: Option<Vec<Addr>>,
-        token_address: Option<Addr>,
-        unstaking_duration: Option<Uint128>,
-        apr: Option<String>,
-        bdog_ratio: Option<Uint128>,
-        gdog_ratio: Option<Uint128>,
-        token_source: Option<Addr>,
-        reward_token_address: Option<Addr>,
     ) -> Result<Response, ContractError> {
-        let mut state = STATE.load(deps.storage)?;
-
-        if state.allowed_operators.contains(&info.sender) == false {
-            return Err(ContractError::Unauthorized {
-                msg: "Only allowed operators can execute this message".to_string(),
-            });
+        let state = STATE.load(deps.storage)?;
+        if !state.emergency_mode {
+            return Err(ContractError::NotInEmergencyMode {});
         }
+        // what was emitted so far is shared by the weight that earned it, if
+        // the reward state is broken the exit goes ahead without it
+        let now = env.block.time.seconds() as u128;
+        let _ = accrue_streams(deps.storage, &deps.querier, &state, now);
+        let stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+        let amount = stake_info.stake_amount + stake_info.unstaking_amount;
 
-        if let Some(allowed_operators) = allowed_operators {
-            state.allowed_operators = allowed_operators
+        let withdrawn = StakeInfo {
+            stake_amount: Uint128::zero(),
+            unstaking_amount: Uint128::zero(),
+            locked_positions: vec![],
+            ..stake_info.clone()
+        };
+        let hooks = save_stake(
+            deps.storage,
+            env.block.height,
+            Some(&stake_info),
+            &withdrawn,
+        )?;
+        stake_list().remove(deps.storage, info.sender.clone())?;
+
+        let mut messages = emergency_hooks(hooks);
+        if !amount.is_zero() {
+            messages.push(SubMsg::new(transfer_msg(
+                &staked_token(&state),
+                &info.sender,
+                amount,
+            )?));
+            record_claim(
+                deps.storage,
+                &env,
+                &info.sender,
+                ClaimKind::Unstaked {},
+                amount,
+            )?;
         }
-        if let Some(token_address) = token_address {
-            state.token_address = token_address
+        Ok(Response::new()
+            .add_submessages(messages)
+            .add_attribute("action", "emergency_withdraw")
+            .add_attribute("amount", amount))
+    }
+
+    pub fn cancel_unstake(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let now = env.block.time.seconds() as u128;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+
+        if !stake_info.unstaking_process {
+            return Err(ContractError::UnstakingProcessIsNotStarted {});
         }
-        if let Some(unstaking_duration) = unstaking_duration {
-            state.unstaking_duration = unstaking_duration
+        if stake_info.unstaking_amount < amount {
+            return Err(ContractError::MoreThanUnstakingAmount {});
         }
-        if let Some(apr) = apr {
-            let now = env.block.time.seconds() as u128;
-
-            let curr_apr = state.gdog_ratio / state.bdog_ratio
-                * Uint128::new(state.apr.parse::<u128>().unwrap());
+        // moving tokens back into stake is a new stake for the gate
+        check_stake_gate(deps.storage, &deps.querier, &state, &info.sender)?;
 
-            let records: StdResult<Vec<_>> = STAKE_LIST
-                .range(deps.storage, None, None, Order::Ascending)
-                .collect();
+        let time = env.block.time;
 
-            state.apr = apr;
-
-            for stake_item in records.unwrap() {
-                let addr = stake_item.0;
-                let stake_data = stake_item.1;
-
-                let reward_with_decimals =
-                    calculate_stake_reward(curr_apr, stake_data.clone(), now);
-                let new_stake_data = StakeInfo {
-                    owner: stake_data.owner,
-                    stake_amount: stake_data.stake_amount,
-                    unstaking_amount: stake_data.unstaking_amount,
-                    reward_amount: stake_data.reward_amount + reward_with_decimals,
-                    apr: state.apr.clone(),
-                    stake_start_time: Uint128::new(now),
-                    reward_start_time: Uint128::new(now),
-                    unstaking_start_time: stake_data.unstaking_start_time,
-                    reward_end_time: stake_data.reward_end_time,
-                    unstaking_process: false,
-                    unstake_end_time: stake_data.unstake_end_time,
-                };
+        // Settle the reward earned by the remaining stake before it grows,
+        // so accrual for the restaked tokens starts from this block.
+        let old = stake_info.clone();
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut stake_info)?;
+        stake_info.stake_amount += amount;
+        stake_info.stake_start_time = time;
 
-                STAKE_LIST.save(deps.storage, addr, &new_stake_data)?;
-            }
-        }
-        if let Some(token_source) = token_source {
-            state.token_source = token_source
-        }
-        if let Some(reward_token_address) = reward_token_address {
-            state.reward_token_address = reward_token_address
-        }
-        if let Some(bdog_ratio) = bdog_ratio {
-            state.bdog_ratio = bdog_ratio
+        stake_info.unstaking_amount -= amount;
+        if stake_info.unstaking_amount == Uint128::zero() {
+            stake_info.unstaking_process = false;
+            stake_info.unstaking_start_time = Timestamp::from_seconds(0);
+            stake_info.unstaking_start_height = 0;
         }
-        if let Some(gdog_ratio) = gdog_ratio {
-            state.gdog_ratio = gdog_ratio
+        let total_stake = total_staked(deps.storage)? + stake_info.stake_amount - old.stake_amount;
+        check_stake_caps(&state.stake_limits, stake_info.stake_amount, total_stake)?;
+
+        let hooks = save_stake(deps.storage, env.block.height, Some(&old), &stake_info)?;
+
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "cancel_unstake")
+            .add_attribute("amount", amount))
+    }
+
+    // Moves unlocked stake to `recipient`, both parties keep the rewards
+    // earned up to now and the stake earns for the recipient from here on
+    pub fn transfer_stake(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        recipient: String,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.transfers_enabled {
+            return Err(ContractError::TransferStakeDisabled {});
+        }
+        let recipient = deps.api.addr_validate(&recipient)?;
+        if recipient == info.sender {
+            return Err(ContractError::SelfTransfer {});
+        }
+        check_stake_gate(deps.storage, &deps.querier, &state, &recipient)?;
+        let now = env.block.time.seconds() as u128;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+
+        let time = env.block.time;
+        let old = stake_info.clone();
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut stake_info)?;
+
+        if stake_info.stake_amount < amount {
+            return Err(ContractError::MoreThanStakeAmount {});
+        }
+        let unlocked = unlocked_amount(&stake_info);
+        if unlocked < amount {
+            return Err(ContractError::MoreThanUnlockedAmount {
+                available: unlocked,
+            });
+        }
+        stake_info.stake_amount -= amount;
+
+        let old_recipient = stake_list().may_load(deps.storage, recipient.clone())?;
+        let mut recipient_info = old_recipient
+            .clone()
+            .unwrap_or_else(|| new_stake_info(recipient.clone(), time));
+        settle(deps.storage, &streams, &mut recipient_info, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut recipient_info)?;
+        recipient_info.stake_amount += amount;
+        recipient_info.stake_start_time = time;
+        let old_amount = old.stake_amount
+            + old_recipient
+                .as_ref()
+                .map(|old| old.stake_amount)
+                .unwrap_or_default();
+        let total_stake =
+            total_staked(deps.storage)? + stake_info.stake_amount + recipient_info.stake_amount
+                - old_amount;
+        check_stake_caps(
+            &state.stake_limits,
+            recipient_info.stake_amount,
+            total_stake,
+        )?;
+
+        let mut hooks = save_stake(deps.storage, env.block.height, Some(&old), &stake_info)?;
+        hooks.extend(save_stake(
+            deps.storage,
+            env.block.height,
+            old_recipient.as_ref(),
+            &recipient_info,
+        )?);
+
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "transfer_stake")
+            .add_attribute("from", info.sender)
+            .add_attribute("to", recipient)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn compound(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let now = env.block.time.seconds() as u128;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+        if !can_compound(&state, &streams) {
+            return Err(ContractError::CompoundNotSupported {});
+        }
+
+        let old = stake_info.clone();
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+        let amount = compound_rewards(deps.storage, &env, &state, &streams, &mut stake_info)?;
+        if amount.is_zero() {
+            return Err(ContractError::NoRewardToCompound {});
+        }
+
+        let hooks = save_stake(deps.storage, env.block.height, Some(&old), &stake_info)?;
+
+        Ok(Response::new()
+            .add_submessages(hooks)
+            .add_attribute("action", "compound")
+            .add_attribute("amount", amount))
+    }
+
+    // Stakes into the pool position held by this contract and mints receipt shares.
+    // The pool compounds its rewards, so every share is worth a growing stake
+    // and rewards follow whoever holds the receipt.
+    pub fn stake_liquid(
+        deps: DepsMut,
+        env: Env,
+        sender: Addr,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let receipt_token = state
+            .receipt_token
+            .clone()
+            .ok_or(ContractError::LiquidStakingDisabled {})?;
+        if state.emergency_mode {
+            return Err(ContractError::EmergencyMode {});
+        }
+        check_stake_gate(deps.storage, &deps.querier, &state, &sender)?;
+        let now = env.block.time.seconds() as u128;
+        let time = env.block.time;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        if !can_compound(&state, &streams) {
+            return Err(ContractError::CompoundNotSupported {});
+        }
+        // share prices only count the staked token, a new share would take part of
+        // what the pool already earned on any other stream
+        if streams
+            .iter()
+            .any(|stream| !is_compoundable(&state, stream))
+        {
+            return Err(ContractError::LiquidStreamNotCompoundable {});
+        }
+
+        let old = LIQUID_POOL.may_load(deps.storage)?;
+        let mut pool = old
+            .clone()
+            .unwrap_or_else(|| new_stake_info(env.contract.address.clone(), time));
+        pool.auto_compound = true;
+        settle(deps.storage, &streams, &mut pool, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut pool)?;
+
+        let supply = RECEIPT_SUPPLY.load(deps.storage)?;
+        let shares = if supply.is_zero() || pool.stake_amount.is_zero() {
+            amount
+        } else {
+            amount.multiply_ratio(supply, pool.stake_amount)
+        };
+
+        // the per address cap counts the sender's own stake and the pool value
+        // of the receipts it already holds
+        let address_stake = match state.stake_limits.max_stake_per_address {
+            Some(_) => {
+                let receipts: BalanceResponse = deps.querier.query_wasm_smart(
+                    &receipt_token,
+                    &Cw20QueryMsg::Balance {
+                        address: sender.to_string(),
+                    },
+                )?;
+                let held = if supply.is_zero() {
+                    Uint128::zero()
+                } else {
+                    receipts.balance.multiply_ratio(pool.stake_amount, supply)
+                };
+                let own = stake_list()
+                    .may_load(deps.storage, sender.clone())?
+                    .map(|stake| stake.stake_amount)
+                    .unwrap_or_default();
+                own + held + amount
+            }
+            None => amount,
+        };
+        let old_amount = old.as_ref().map(|old| old.stake_amount).unwrap_or_default();
+        let total_stake = total_staked(deps.storage)? + pool.stake_amount + amount - old_amount;
+        check_stake_limits(&state.stake_limits, amount, address_stake, total_stake)?;
+
+        pool.stake_amount += amount;
+        pool.stake_start_time = time;
+        save_pool(deps.storage, old.as_ref(), &pool)?;
+        RECEIPT_SUPPLY.save(deps.storage, &(supply + shares))?;
+
+        let mint_msg = WasmMsg::Execute {
+            contract_addr: receipt_token.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Mint {
+                recipient: sender.to_string(),
+                amount: shares,
+            })?,
+            funds: vec![],
+        };
+        Ok(Response::new()
+            .add_message(mint_msg)
+            .add_attribute("action", "stake_liquid")
+            .add_attribute("staker", sender)
+            .add_attribute("amount", amount)
+            .add_attribute("shares", shares))
+    }
+
+    // Burns received receipt shares and moves their value from the pool into the
+    // holder's unstaking amount, or pays it out in emergency mode. The pool only
+    // compounds the streams paid in the staked token, the holder is paid its
+    // share of the others.
+    pub fn unstake_liquid(
+        deps: DepsMut,
+        env: Env,
+        holder: Addr,
+        shares: Uint128,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let receipt_token = state
+            .receipt_token
+            .clone()
+            .ok_or(ContractError::LiquidStakingDisabled {})?;
+        let now = env.block.time.seconds() as u128;
+        let time = env.block.time;
+        let supply = RECEIPT_SUPPLY.load(deps.storage)?;
+        let remaining_supply = supply.checked_sub(shares)?;
+        let burn_msg = WasmMsg::Execute {
+            contract_addr: receipt_token.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Burn { amount: shares })?,
+            funds: vec![],
+        };
+
+        // in emergency mode pending rewards are given up and the value is paid
+        // out right away, without unbonding
+        if state.emergency_mode {
+            let _ = accrue_streams(deps.storage, &deps.querier, &state, now);
+            let mut pool = LIQUID_POOL.load(deps.storage)?;
+            let old_pool = pool.clone();
+            let amount = shares.multiply_ratio(pool.stake_amount, supply);
+            pool.stake_amount -= amount;
+            save_pool(deps.storage, Some(&old_pool), &pool)?;
+            RECEIPT_SUPPLY.save(deps.storage, &remaining_supply)?;
+            let mut messages = vec![];
+            if !amount.is_zero() {
+                messages.push(SubMsg::new(transfer_msg(
+                    &staked_token(&state),
+                    &holder,
+                    amount,
+                )?));
+                record_claim(deps.storage, &env, &holder, ClaimKind::Unstaked {}, amount)?;
+            }
+            return Ok(Response::new()
+                .add_message(burn_msg)
+                .add_submessages(messages)
+                .add_attribute("action", "unstake_liquid")
+                .add_attribute("holder", holder)
+                .add_attribute("shares", shares)
+                .add_attribute("amount", amount));
+        }
+
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut pool = LIQUID_POOL.load(deps.storage)?;
+        let old_pool = pool.clone();
+        settle(deps.storage, &streams, &mut pool, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut pool)?;
+
+        let amount = shares.multiply_ratio(pool.stake_amount, supply);
+        pool.stake_amount -= amount;
+
+        let recipient = REWARD_BENEFICIARIES
+            .may_load(deps.storage, &holder)?
+            .unwrap_or_else(|| holder.clone());
+        let mut messages = vec![];
+        for stream in streams.iter().filter(|s| !is_compoundable(&state, s)) {
+            let reward = pool.rewards.iter_mut().find(|r| r.stream_id == stream.id);
+            if let Some(reward) = reward.filter(|r| !r.pending.is_zero()) {
+                let share = reward.pending.multiply_ratio(shares, supply);
+                let paid = take_from_reserve(deps.storage, &stream.token, share)?;
+                if !paid.is_zero() {
+                    messages.push(SubMsg::new(transfer_msg(&stream.token, &recipient, paid)?));
+                    reward.pending -= paid;
+                    record_claim(
+                        deps.storage,
+                        &env,
+                        &holder,
+                        ClaimKind::Reward {
+                            stream_id: stream.id,
+                            token: stream.token.clone(),
+                        },
+                        paid,
+                    )?;
+                }
+            }
+        }
+        save_pool(deps.storage, Some(&old_pool), &pool)?;
+        RECEIPT_SUPPLY.save(deps.storage, &remaining_supply)?;
+
+        let old = stake_list().may_load(deps.storage, holder.clone())?;
+        let mut stake_info = old
+            .clone()
+            .unwrap_or_else(|| new_stake_info(holder.clone(), time));
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+        auto_compound(deps.storage, &env, &state, &streams, &mut stake_info)?;
+        stake_info.unstaking_process = true;
+        stake_info.unstaking_start_time = time;
+        stake_info.unstaking_start_height = env.block.height;
+        stake_info.unstaking_amount += amount;
+        messages.extend(save_stake(
+            deps.storage,
+            env.block.height,
+            old.as_ref(),
+            &stake_info,
+        )?);
+
+        Ok(Response::new()
+            .add_message(burn_msg)
+            .add_submessages(messages)
+            .add_attribute("action", "unstake_liquid")
+            .add_attribute("holder", holder)
+            .add_attribute("shares", shares)
+            .add_attribute("amount", amount))
+    }
+
+    pub fn set_auto_compound(
+        deps: DepsMut,
+        info: MessageInfo,
+        enabled: bool,
+    ) -> Result<Response, ContractError> {
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+        let state = STATE.load(deps.storage)?;
+        let streams = REWARD_STREAMS.load(deps.storage)?;
+        if enabled && !can_compound(&state, &streams) {
+            return Err(ContractError::CompoundNotSupported {});
+        }
+
+        stake_info.auto_compound = enabled;
+        stake_list().save(deps.storage, info.sender, &stake_info)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "set_auto_compound")
+            .add_attribute("enabled", enabled.to_string()))
+    }
+
+    // Restakes the settled rewards paid in the staked token, returns the restaked amount.
+    // Restaked rewards leave the reward reserve and become principal.
+    fn compound_rewards(
+        storage: &mut dyn Storage,
+        env: &Env,
+        state: &State,
+        streams: &[RewardStream],
+        stake_info: &mut StakeInfo,
+    ) -> StdResult<Uint128> {
+        let mut amount = Uint128::zero();
+        for stream in streams.iter().filter(|s| is_compoundable(state, s)) {
+            if let Some(reward) = stake_info
+                .rewards
+                .iter_mut()
+                .find(|r| r.stream_id == stream.id)
+            {
+                let covered = take_from_reserve(storage, &stream.token, reward.pending)?;
+                if !covered.is_zero() {
+                    record_claim(
+                        storage,
+                        env,
+                        &stake_info.owner,
+                        ClaimKind::Compounded {
+                            stream_id: stream.id,
+                            token: stream.token.clone(),
+                        },
+                        covered,
+                    )?;
+                }
+                amount += covered;
+                reward.pending -= covered;
+            }
+        }
+        stake_info.stake_amount += amount;
+        Ok(amount)
+    }
+
+    // Compounds only for users who opted in
+    fn auto_compound(
+        storage: &mut dyn Storage,
+        env: &Env,
+        state: &State,
+        streams: &[RewardStream],
+        stake_info: &mut StakeInfo,
+    ) -> StdResult<Uint128> {
+        if !stake_info.auto_compound {
+            return Ok(Uint128::zero());
+        }
+        compound_rewards(storage, env, state, streams, stake_info)
+    }
+
+    pub fn claim_reward(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        amount: Option<Uint128>,
+        recipient: Option<String>,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        let now = env.block.time.seconds() as u128;
+        let streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let mut stake_info = stake_list().load(deps.storage, info.sender.clone())?;
+        let beneficiary = REWARD_BENEFICIARIES.may_load(deps.storage, &info.sender)?;
+        let recipient = match (beneficiary, recipient) {
+            (Some(_), Some(_)) => return Err(ContractError::RecipientWithBeneficiary {}),
+            (Some(beneficiary), None) => beneficiary,
+            (None, Some(recipient)) => deps.api.addr_validate(&recipient)?,
+            (None, None) => info.sender.clone(),
+        };
+
+        let old = stake_info.clone();
+        settle(deps.storage, &streams, &mut stake_info, now)?;
+
+        // Each stream pays up to the requested amount what its reserve can cover,
+        // the rest stays accrued
+        let mut messages = vec![];
+        let mut attributes = vec![];
+        for stream in &streams {
+            let reward = stake_info
+                .rewards
+                .iter_mut()
+                .find(|r| r.stream_id == stream.id);
+            if let Some(reward) = reward.filter(|r| !r.pending.is_zero()) {
+                let requested = amount.map_or(reward.pending, |amount| amount.min(reward.pending));
+                let paid = take_from_reserve(deps.storage, &stream.token, requested)?;
+                if !paid.is_zero() {
+                    let fee_payout_msg = transfer_msg(&stream.token, &recipient, paid)?;
+                    messages.push(SubMsg::new(fee_payout_msg));
+                    reward.pending -= paid;
+                    record_claim(
+                        deps.storage,
+                        &env,
+                        &info.sender,
+                        ClaimKind::Reward {
+                            stream_id: stream.id,
+                            token: stream.token.clone(),
+                        },
+                        paid,
+                    )?;
+                }
+                attributes.push(("stream_id", stream.id.to_string()));
+                attributes.push(("paid", paid.to_string()));
+                attributes.push(("remaining", reward.pending.to_string()));
+            }
+        }
+
+        let hooks = save_stake(deps.storage, env.block.height, Some(&old), &stake_info)?;
+        messages.extend(claim_hooks(deps.storage, &old, &stake_info, hooks)?);
+        Ok(Response::new()
+            .add_submessages(messages)
+            .add_attribute("method", "distribute_reward")
+            .add_attribute("recipient", recipient)
+            .add_attributes(attributes))
+    }
+
+    pub fn set_reward_beneficiary(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: Option<String>,
+    ) -> Result<Response, ContractError> {
+        let beneficiary = match addr {
+            Some(addr) => deps.api.addr_validate(&addr)?,
+            None => info.sender.clone(),
+        };
+        if beneficiary == info.sender {
+            REWARD_BENEFICIARIES.remove(deps.storage, &info.sender);
+        } else {
+            REWARD_BENEFICIARIES.save(deps.storage, &info.sender, &beneficiary)?;
+        }
+
+        Ok(Response::new()
+            .add_attribute("action", "set_reward_beneficiary")
+            .add_attribute("staker", info.sender)
+            .add_attribute("beneficiary", beneficiary))
+    }
+
+    pub fn fund_rewards(
+        deps: DepsMut,
+        sender: Addr,
+        funds: Vec<(Denom, Uint128)>,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.allowed_operators.contains(&sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can fund rewards".to_string(),
+            });
+        }
+
+        let streams = REWARD_STREAMS.load(deps.storage)?;
+        let mut response = Response::new().add_attribute("action", "fund_rewards");
+        for (token, amount) in funds {
+            if !streams.iter().any(|stream| stream.token == token) {
+                return Err(ContractError::InvalidToken {});
+            }
+            let key = denom_key(&token);
+            let reserve = REWARD_RESERVES
+                .may_load(deps.storage, &key)?
+                .unwrap_or_default();
+            REWARD_RESERVES.save(deps.storage, &key, &(reserve + amount))?;
+            response = response.add_attribute(key, amount);
+        }
+        Ok(response)
+    }
+
+    // Synthetix style notify: the deposit plus whatever is left of the running
+    // period is spread over a new period starting now
+    pub fn notify_reward_amount(
+        deps: DepsMut,
+        env: Env,
+        sender: Addr,
+        stream_id: u64,
+        token: Denom,
+        amount: Uint128,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.allowed_operators.contains(&sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can notify rewards".to_string(),
+            });
+        }
+        let duration = match state.reward_mode {
+            RewardMode::FixedEmission { duration } => duration,
+            RewardMode::Apr {} => return Err(ContractError::InvalidRewardMode {}),
+        };
+
+        let now = env.block.time.seconds() as u128;
+        let mut streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        // in epoch mode the period starts with the current epoch, which is not accrued yet
+        let time = Uint128::new(accrued_until(deps.storage, now)?);
+        let stream = streams
+            .iter_mut()
+            .find(|s| s.id == stream_id)
+            .ok_or(ContractError::RewardStreamNotFound { id: stream_id })?;
+        if stream.token != token {
+            return Err(ContractError::InvalidToken {});
+        }
+
+        let leftover = match stream.end_time {
+            Some(end_time) if end_time > time && stream.start_time <= time => {
+                (end_time - time) * stream.rate
+            }
+            _ => Uint128::zero(),
+        };
+        stream.rate = Decimal::checked_from_ratio(amount + leftover, duration)
+            .map_err(|_| ContractError::InvalidEmissionDuration {})?;
+        stream.start_time = time;
+        stream.end_time = Some(time + duration);
+        stream.last_update_time = time;
+        let period_end = time + duration;
+        REWARD_STREAMS.save(deps.storage, &streams)?;
+
+        let key = denom_key(&token);
+        let reserve = REWARD_RESERVES
+            .may_load(deps.storage, &key)?
+            .unwrap_or_default();
+        REWARD_RESERVES.save(deps.storage, &key, &(reserve + amount))?;
+
+        Ok(Response::new()
+            .add_attribute("action", "notify_reward_amount")
+            .add_attribute("stream_id", stream_id.to_string())
+            .add_attribute("amount", amount)
+            .add_attribute("period_end", period_end))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn edit_state(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        allowed_operators: Option<Vec<Addr>>,
+        token_address: Option<Addr>,
+        unstaking_duration: Option<Duration>,
+        bdog_ratio: Option<Uint128>,
+        gdog_ratio: Option<Uint128>,
+        dog_burn_address: Option<Addr>,
+        gdog_address: Option<Addr>,
+        token_source: Option<Addr>,
+        lockup_tiers: Option<Vec<LockupTier>>,
+        instant_unstake_penalty: Option<PenaltyConfig>,
+        transfers_enabled: Option<bool>,
+        stake_limits: Option<StakeLimits>,
+        receipt_token: Option<Addr>,
+        emergency_mode: Option<bool>,
+        stake_gate: Option<StakeGate>,
+    ) -> Result<Response, ContractError> {
+        let mut state = STATE.load(deps.storage)?;
+
+        if !state.allowed_operators.contains(&info.sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can execute this message".to_string(),
+            });
+        }
+
+        if let Some(allowed_operators) = allowed_operators {
+            state.allowed_operators = allowed_operators
+        }
+        if let Some(token_address) = token_address {
+            state.token_address = token_address
+        }
+        if let Some(unstaking_duration) = unstaking_duration {
+            state.unstaking_duration = unstaking_duration
+        }
+        if let Some(token_source) = token_source {
+            state.token_source = token_source
+        }
+        // running positions keep the boost and term they were locked with
+        if let Some(penalty) = instant_unstake_penalty {
+            validate_penalty(&penalty)?;
+            state.instant_unstake_penalty = Some(penalty)
+        }
+        if let Some(transfers_enabled) = transfers_enabled {
+            state.transfers_enabled = transfers_enabled
+        }
+        if let Some(stake_limits) = stake_limits {
+            state.stake_limits = stake_limits
+        }
+        if let Some(receipt_token) = receipt_token {
+            state.receipt_token = Some(receipt_token)
+        }
+        if let Some(emergency_mode) = emergency_mode {
+            state.emergency_mode = emergency_mode
+        }
+        if let Some(stake_gate) = stake_gate {
+            state.stake_gate = stake_gate
+        }
+        if let Some(lockup_tiers) = lockup_tiers {
+            validate_lockup_tiers(&lockup_tiers)?;
+            state.lockup_tiers = lockup_tiers
+        }
+        if bdog_ratio.is_some()
+            || gdog_ratio.is_some()
+            || dog_burn_address.is_some()
+            || gdog_address.is_some()
+        {
+            // rewards up to now are accrued with the previous multiplier
+            let now = env.block.time.seconds() as u128;
+            accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        }
+        if let Some(bdog_ratio) = bdog_ratio {
+            state.bdog_ratio = bdog_ratio
+        }
+        if let Some(gdog_ratio) = gdog_ratio {
+            state.gdog_ratio = gdog_ratio
+        }
+        if let Some(dog_burn_address) = dog_burn_address {
+            state.dog_burn_address = Some(dog_burn_address)
+        }
+        if let Some(gdog_address) = gdog_address {
+            state.gdog_address = Some(gdog_address)
         }
         STATE.save(deps.storage, &state)?;
         Ok(Response::new().add_attribute("action", "increment"))
     }
+
+    pub fn update_allowlist(
+        deps: DepsMut,
+        info: MessageInfo,
+        add: Vec<String>,
+        remove: Vec<String>,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.allowed_operators.contains(&info.sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can execute this message".to_string(),
+            });
+        }
+
+        for addr in &add {
+            let addr = deps.api.addr_validate(addr)?;
+            ALLOWLIST.save(deps.storage, &addr, &Empty {})?;
+        }
+        for addr in &remove {
+            let addr = deps.api.addr_validate(addr)?;
+            ALLOWLIST.remove(deps.storage, &addr);
+        }
+
+        Ok(Response::new()
+            .add_attribute("action", "update_allowlist")
+            .add_attribute("added", add.len().to_string())
+            .add_attribute("removed", remove.len().to_string()))
+    }
+
+    pub fn add_hook(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.allowed_operators.contains(&info.sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can execute this message".to_string(),
+            });
+        }
+
+        let addr = deps.api.addr_validate(&addr)?;
+        let mut hooks = HOOKS.may_load(deps.storage)?.unwrap_or_default();
+        if hooks.contains(&addr) {
+            return Err(ContractError::HookAlreadyRegistered {});
+        }
+        hooks.push(addr.clone());
+        HOOKS.save(deps.storage, &hooks)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "add_hook")
+            .add_attribute("hook", addr))
+    }
+
+    pub fn remove_hook(
+        deps: DepsMut,
+        info: MessageInfo,
+        addr: String,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.allowed_operators.contains(&info.sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can execute this message".to_string(),
+            });
+        }
+
+        let addr = deps.api.addr_validate(&addr)?;
+        let mut hooks = HOOKS.may_load(deps.storage)?.unwrap_or_default();
+        if !hooks.contains(&addr) {
+            return Err(ContractError::HookNotRegistered {});
+        }
+        hooks.retain(|hook| *hook != addr);
+        HOOKS.save(deps.storage, &hooks)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "remove_hook")
+            .add_attribute("hook", addr))
+    }
+
+    pub fn add_reward_stream(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        msg: RewardStreamMsg,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.allowed_operators.contains(&info.sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can execute this message".to_string(),
+            });
+        }
+
+        let now = env.block.time.seconds() as u128;
+        let mut streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let stream = new_reward_stream(streams.len() as u64, msg, now)?;
+        let id = stream.id;
+        streams.push(stream);
+        REWARD_STREAMS.save(deps.storage, &streams)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "add_reward_stream")
+            .add_attribute("stream_id", id.to_string()))
+    }
+
+    pub fn update_reward_stream(
+        deps: DepsMut,
+        env: Env,
+        info: MessageInfo,
+        id: u64,
+        rate: Option<Decimal>,
+        end_time: Option<Uint128>,
+    ) -> Result<Response, ContractError> {
+        let state = STATE.load(deps.storage)?;
+        if !state.allowed_operators.contains(&info.sender) {
+            return Err(ContractError::Unauthorized {
+                msg: "Only allowed operators can execute this message".to_string(),
+            });
+        }
+
+        // rewards up to now are accrued with the previous rate
+        let now = env.block.time.seconds() as u128;
+        let mut streams = accrue_streams(deps.storage, &deps.querier, &state, now)?;
+        let stream = streams
+            .iter_mut()
+            .find(|s| s.id == id)
+            .ok_or(ContractError::RewardStreamNotFound { id })?;
+        // a fixed emission schedule only follows from the notified budget
+        if (rate.is_some() || end_time.is_some()) && state.reward_mode != (RewardMode::Apr {}) {
+            return Err(ContractError::InvalidRewardMode {});
+        }
+        if let Some(rate) = rate {
+            stream.rate = rate;
+        }
+        if let Some(end_time) = end_time {
+            if end_time < Uint128::new(now) || end_time <= stream.start_time {
+                return Err(ContractError::InvalidRewardStream {
+                    msg: "end time must be in the future and after start time".to_string(),
+                });
+            }
+            stream.end_time = Some(end_time);
+        }
+        REWARD_STREAMS.save(deps.storage, &streams)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "update_reward_stream")
+            .add_attribute("stream_id", id.to_string()))
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(_deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        EMERGENCY_HOOK_REPLY_ID => Ok(Response::new().add_attribute("action", "hook_failed")),
+        id => Err(ContractError::UnknownReplyId { id }),
+    }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -361,7 +2061,36 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetUserReward { addr } => to_binary(&query::user_reward(deps, env, addr)?),
         QueryMsg::GetUserStakeInfo { addr } => to_binary(&query::user_stake_info(deps, addr)?),
-        QueryMsg::GetState {} => to_binary(&query::state(deps)?),
+        QueryMsg::GetUserPositions { addr } => to_binary(&query::user_positions(deps, env, addr)?),
+        QueryMsg::GetState {} => to_binary(&query::state(deps, env)?),
+        QueryMsg::VotingPowerAtHeight { address, height } => {
+            to_binary(&query::voting_power_at_height(deps, env, address, height)?)
+        }
+        QueryMsg::TotalPowerAtHeight { height } => {
+            to_binary(&query::total_power_at_height(deps, env, height)?)
+        }
+        QueryMsg::SimulateInstantUnstake { amount } => {
+            to_binary(&query::simulate_instant_unstake(deps, amount)?)
+        }
+        QueryMsg::TopStakers { start_after, limit } => {
+            to_binary(&query::top_stakers(deps, start_after, limit)?)
+        }
+        QueryMsg::PoolInfo {} => to_binary(&query::pool_info(deps, env)?),
+        QueryMsg::Epoch {} => to_binary(&query::epoch(deps, env)?),
+        QueryMsg::Hooks {} => to_binary(&query::hooks(deps)?),
+        QueryMsg::RewardBeneficiary { addr } => to_binary(&query::reward_beneficiary(deps, addr)?),
+        QueryMsg::Allowlist { start_after, limit } => {
+            to_binary(&query::allowlist(deps, start_after, limit)?)
+        }
+        QueryMsg::CanStake { addr } => to_binary(&query::can_stake(deps, addr)?),
+        QueryMsg::RewardHistory {
+            addr,
+            start_after,
+            limit,
+        } => to_binary(&query::reward_history(deps, addr, start_after, limit)?),
+        QueryMsg::Stats { start_after, limit } => {
+            to_binary(&query::stats(deps, start_after, limit)?)
+        }
         QueryMsg::RangeStakeList { start_after, limit } => {
             to_binary(&query::list(deps, start_after, limit)?)
         }
@@ -370,9 +2099,16 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
 
 pub mod query {
     use super::*;
+    use crate::helper::{is_stream_active, yearly_emission, SECONDS_PER_YEAR};
     use crate::msg::{
-        GetStakeResponse, GetStateResponse, GetUserRewardResponse, StakeListResponse,
+        AllowlistResponse, CanStakeResponse, ClaimRecordResponse, EpochResponse, GetStakeResponse,
+        GetStateResponse, GetUserRewardResponse, HooksResponse, PoolInfoResponse, PositionResponse,
+        RewardAmount, RewardBeneficiaryResponse, RewardHistoryResponse, RewardReserveResponse,
+        SimulateInstantUnstakeResponse, StakeListResponse, StatsResponse, StreamAprResponse,
+        TotalPowerAtHeightResponse, UserPositionsResponse, UserStatsResponse,
+        VotingPowerAtHeightResponse,
     };
+    use cosmwasm_std::StdError;
     use cosmwasm_std::{Addr, Order};
     use cw_storage_plus::Bound;
 
@@ -380,39 +2116,330 @@ pub mod query {
     const MAX_LIMIT: u32 = 30;
 
     pub fn user_reward(deps: Deps, env: Env, addr: Addr) -> StdResult<GetUserRewardResponse> {
-        let stake_info = STAKE_LIST.load(deps.storage, addr)?;
+        let mut stake_info = stake_list().load(deps.storage, addr)?;
         let state = STATE.load(deps.storage)?;
 
         let now = env.block.time.seconds() as u128;
-        let mut total = stake_info.reward_amount;
-        let apr =
-            state.gdog_ratio / state.bdog_ratio * Uint128::new(state.apr.parse::<u128>().unwrap());
-        if stake_info.stake_amount > Uint128::zero() {
-            let curr_reward = calculate_stake_reward(apr, stake_info, now);
-            total = total + curr_reward;
-        }
+        let Accrued {
+            streams,
+            checkpoints,
+            ..
+        } = accrued_streams(deps.storage, &deps.querier, &state, now)?;
+        settle_epoch(deps.storage, &checkpoints, &mut stake_info)?;
+        settle_reward(&streams, &mut stake_info, now);
+
+        let rewards = streams
+            .into_iter()
+            .map(|stream| RewardAmount {
+                stream_id: stream.id,
+                amount: stake_info
+                    .rewards
+                    .iter()
+                    .find(|r| r.stream_id == stream.id)
+                    .map(|r| r.pending)
+                    .unwrap_or_default(),
+                token: stream.token,
+            })
+            .collect();
 
-        Ok(GetUserRewardResponse { amount: total })
+        Ok(GetUserRewardResponse { rewards })
+    }
+
+    pub fn epoch(deps: Deps, env: Env) -> StdResult<EpochResponse> {
+        let state = STATE.load(deps.storage)?;
+        let now = env.block.time.seconds() as u128;
+        let epoch = accrued_streams(deps.storage, &deps.querier, &state, now)?
+            .epoch
+            .ok_or_else(|| StdError::generic_err("Epoch mode is not enabled"))?;
+        Ok(EpochResponse {
+            epoch: epoch.epoch,
+            start: epoch.epoch_start(epoch.epoch),
+            end: epoch.epoch_start(epoch.epoch + 1),
+            eligible_weight: epoch.eligible_weight,
+        })
     }
 
     pub fn user_stake_info(deps: Deps, addr: Addr) -> StdResult<GetStakeResponse> {
-        let info = STAKE_LIST.load(deps.storage, addr)?;
+        let info = stake_list().load(deps.storage, addr)?;
         Ok(GetStakeResponse { info })
     }
 
-    pub fn state(deps: Deps) -> StdResult<GetStateResponse> {
+    pub fn user_positions(deps: Deps, env: Env, addr: Addr) -> StdResult<UserPositionsResponse> {
+        let info = stake_list().load(deps.storage, addr)?;
+        let now = env.block.time;
+        let positions = info
+            .locked_positions
+            .iter()
+            .map(|position| PositionResponse {
+                tier_id: position.tier_id,
+                amount: position.amount,
+                boost: position.boost,
+                unlock_time: position.unlock_time,
+                unlocked: position.unlock_time <= now,
+            })
+            .collect();
+        // expired positions are released by the next transaction
+        let unlocked_amount = unlocked_amount(&info)
+            + info
+                .locked_positions
+                .iter()
+                .filter(|position| position.unlock_time <= now)
+                .map(|position| position.amount)
+                .sum::<Uint128>();
+        Ok(UserPositionsResponse {
+            unlocked_amount,
+            positions,
+        })
+    }
+
+    pub fn voting_power_at_height(
+        deps: Deps,
+        env: Env,
+        address: String,
+        height: Option<u64>,
+    ) -> StdResult<VotingPowerAtHeightResponse> {
+        let address = deps.api.addr_validate(&address)?;
+        let height = height.unwrap_or(env.block.height);
+        let power = STAKED_BALANCES
+            .may_load_at_height(deps.storage, &address, height)?
+            .unwrap_or_default();
+        Ok(VotingPowerAtHeightResponse { power, height })
+    }
+
+    pub fn total_power_at_height(
+        deps: Deps,
+        env: Env,
+        height: Option<u64>,
+    ) -> StdResult<TotalPowerAtHeightResponse> {
+        let height = height.unwrap_or(env.block.height);
+        let power = STAKED_TOTAL
+            .may_load_at_height(deps.storage, height)?
+            .unwrap_or_default();
+        Ok(TotalPowerAtHeightResponse { power, height })
+    }
+
+    pub fn simulate_instant_unstake(
+        deps: Deps,
+        amount: Uint128,
+    ) -> StdResult<SimulateInstantUnstakeResponse> {
+        let state = STATE.load(deps.storage)?;
+        let bps = state
+            .instant_unstake_penalty
+            .map(|config| config.bps)
+            .ok_or_else(|| StdError::generic_err("Instant unstake is disabled"))?;
+        let (penalty, payout) = instant_unstake_penalty(amount, bps);
+        Ok(SimulateInstantUnstakeResponse {
+            amount,
+            penalty,
+            payout,
+        })
+    }
+
+    pub fn state(deps: Deps, env: Env) -> StdResult<GetStateResponse> {
         let state = STATE.load(deps.storage)?;
-        let total_staked = STAKED_TOTAL.load(deps.storage)?;
-        let total_reward = REWARD_TOTAL.load(deps.storage)?;
+        let total_staked = total_staked(deps.storage)?;
+        let rewards_claimed = TOTAL_STATS
+            .may_load(deps.storage)?
+            .unwrap_or_default()
+            .rewards_claimed;
+        let receipt_supply = RECEIPT_SUPPLY.may_load(deps.storage)?.unwrap_or_default();
+        let reward_streams = REWARD_STREAMS.load(deps.storage)?;
+        let total_weight = TOTAL_WEIGHT.load(deps.storage)?;
+        let now = Uint128::new(env.block.time.seconds() as u128);
+        let multiplier = get_multiplier(&deps.querier, &state);
+
+        let mut reward_reserves: Vec<RewardReserveResponse> = vec![];
+        for stream in &reward_streams {
+            if reward_reserves.iter().any(|r| r.token == stream.token) {
+                continue;
+            }
+            let amount = REWARD_RESERVES
+                .may_load(deps.storage, &denom_key(&stream.token))?
+                .unwrap_or_default();
+            // yearly emission of every running stream paying this token
+            let yearly_emission: Uint128 = reward_streams
+                .iter()
+                .filter(|s| s.token == stream.token && is_stream_active(s, now))
+                .map(|s| yearly_emission(s, &state.reward_mode, multiplier, total_weight))
+                .sum();
+            let runway_seconds = if yearly_emission.is_zero() {
+                None
+            } else {
+                Some(amount.multiply_ratio(SECONDS_PER_YEAR, yearly_emission))
+            };
+            reward_reserves.push(RewardReserveResponse {
+                token: stream.token.clone(),
+                amount,
+                runway_seconds,
+            });
+        }
+
         Ok(GetStateResponse {
             allowed_operators: state.allowed_operators,
             token_address: state.token_address,
+            stake_denom: state.stake_denom,
             unstaking_duration: state.unstaking_duration,
-            apr: state.apr,
             token_source: state.token_source,
+            bdog_ratio: state.bdog_ratio,
+            gdog_ratio: state.gdog_ratio,
+            dog_burn_address: state.dog_burn_address,
+            gdog_address: state.gdog_address,
+            multiplier,
+            total_staked,
+            rewards_claimed,
+            reward_mode: state.reward_mode,
+            lockup_tiers: state.lockup_tiers,
+            instant_unstake_penalty: state.instant_unstake_penalty,
+            transfers_enabled: state.transfers_enabled,
+            stake_limits: state.stake_limits,
+            receipt_token: state.receipt_token,
+            receipt_supply,
+            emergency_mode: state.emergency_mode,
+            stake_gate: state.stake_gate,
+            reward_streams,
+            reward_reserves,
+        })
+    }
+
+    pub fn reward_history(
+        deps: Deps,
+        addr: Addr,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> StdResult<RewardHistoryResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.map(Bound::exclusive);
+
+        let records = CLAIM_HISTORY
+            .prefix(&addr)
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(id, record)| ClaimRecordResponse { id, record }))
+            .collect::<StdResult<_>>()?;
+
+        Ok(RewardHistoryResponse { records })
+    }
+
+    pub fn stats(
+        deps: Deps,
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    ) -> StdResult<StatsResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.as_ref().map(Bound::exclusive);
+
+        let total = TOTAL_STATS.may_load(deps.storage)?.unwrap_or_default();
+        let users = USER_STATS
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(addr, stats)| UserStatsResponse { addr, stats }))
+            .collect::<StdResult<_>>()?;
+
+        Ok(StatsResponse { total, users })
+    }
+
+    pub fn hooks(deps: Deps) -> StdResult<HooksResponse> {
+        let hooks = HOOKS.may_load(deps.storage)?.unwrap_or_default();
+        Ok(HooksResponse { hooks })
+    }
+
+    pub fn reward_beneficiary(deps: Deps, addr: Addr) -> StdResult<RewardBeneficiaryResponse> {
+        let beneficiary = REWARD_BENEFICIARIES
+            .may_load(deps.storage, &addr)?
+            .unwrap_or(addr);
+        Ok(RewardBeneficiaryResponse { beneficiary })
+    }
+
+    pub fn allowlist(
+        deps: Deps,
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    ) -> StdResult<AllowlistResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.as_ref().map(Bound::exclusive);
+        let addresses = ALLOWLIST
+            .keys(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<_>>()?;
+        Ok(AllowlistResponse { addresses })
+    }
+
+    pub fn can_stake(deps: Deps, addr: Addr) -> StdResult<CanStakeResponse> {
+        let state = STATE.load(deps.storage)?;
+        let can_stake = super::can_stake(deps.storage, &deps.querier, &state, &addr)?;
+        Ok(CanStakeResponse { can_stake })
+    }
+
+    // Stakers from the largest stake down, paged after the stake of `start_after`
+    pub fn top_stakers(
+        deps: Deps,
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    ) -> StdResult<StakeListResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let max = match start_after {
+            Some(addr) => {
+                let info = stake_list().load(deps.storage, addr.clone())?;
+                Some(Bound::exclusive((info.stake_amount.u128(), addr)))
+            }
+            None => None,
+        };
+
+        let stake_list = stake_list()
+            .idx
+            .amount
+            .range(deps.storage, None, max, Order::Descending)
+            .map(|item| item.map(|(_, info)| info))
+            // fully unstaked addresses stay listed with nothing staked, they sort last
+            .take_while(|item| !matches!(item, Ok(info) if info.stake_amount.is_zero()))
+            .take(limit)
+            .collect::<StdResult<_>>()?;
+
+        Ok(StakeListResponse { stake_list })
+    }
+
+    pub fn pool_info(deps: Deps, env: Env) -> StdResult<PoolInfoResponse> {
+        let state = STATE.load(deps.storage)?;
+        let total_staked = total_staked(deps.storage)?;
+        let total_unbonding = UNBONDING_TOTAL.may_load(deps.storage)?.unwrap_or_default();
+        let total_weight = TOTAL_WEIGHT.load(deps.storage)?;
+        let streams = REWARD_STREAMS.load(deps.storage)?;
+        let now = Uint128::new(env.block.time.seconds() as u128);
+        let multiplier = get_multiplier(&deps.querier, &state);
+
+        // reward per year of one unboosted staked token
+        let stream_aprs = streams
+            .iter()
+            .map(|stream| {
+                let apr = if !is_stream_active(stream, now) {
+                    Decimal::zero()
+                } else {
+                    match state.reward_mode {
+                        RewardMode::Apr {} => stream.rate * multiplier,
+                        RewardMode::FixedEmission { .. } if total_weight.is_zero() => {
+                            Decimal::zero()
+                        }
+                        RewardMode::FixedEmission { .. } => Decimal::from_ratio(
+                            yearly_emission(stream, &state.reward_mode, multiplier, total_weight),
+                            total_weight,
+                        ),
+                    }
+                };
+                StreamAprResponse {
+                    stream_id: stream.id,
+                    token: stream.token.clone(),
+                    apr,
+                }
+            })
+            .collect();
+
+        Ok(PoolInfoResponse {
+            stakers: STAKER_COUNT.may_load(deps.storage)?.unwrap_or_default(),
             total_staked,
-            total_reward,
-            reward_token_address: state.reward_token_address,
+            total_unbonding,
+            staked_token: staked_token(&state),
+            tvl: total_staked + total_unbonding,
+            stream_aprs,
         })
     }
 
@@ -424,7 +2451,7 @@ pub mod query {
         let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
         let start = start_after.map(Bound::exclusive);
 
-        let records: StdResult<Vec<_>> = STAKE_LIST
+        let records: StdResult<Vec<_>> = stake_list()
             .range(deps.storage, start, None, Order::Ascending)
             .take(limit)
             .collect();
@@ -1,5 +1,5 @@
 use cosmwasm_std::{DivideByZeroError, OverflowError, StdError, Uint128};
-use cw_utils::PaymentError;
+use cw_utils::{Expiration, PaymentError};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -13,8 +13,8 @@ pub enum ContractError {
     #[error("{0}")]
     Payment(#[from] PaymentError),
 
-    #[error("Min unstaking time required")]
-    MinUnstakingTimeRequired {},
+    #[error("Unstaked tokens are released at {release}")]
+    MinUnstakingTimeRequired { release: Expiration },
 
     #[error("Unsupported  CW20")]
     InvalidToken {},
@@ -25,6 +25,9 @@ pub enum ContractError {
     #[error("Requested unstake amount is larger than staked amount")]
     MoreThanStakeAmount {},
 
+    #[error("Requested cancel amount is larger than unstaking amount")]
+    MoreThanUnstakingAmount {},
+
     #[error("Unstaking process is ongoing")]
     OngoingUnstakingProcess {},
 
@@ -34,9 +37,102 @@ pub enum ContractError {
     #[error("Claim amount greater than reward: {reward} and ask: {amount}")]
     AmountLargerThanReward { reward: Uint128, amount: Uint128 },
 
+    #[error("Compounding requires the reward token to be the staked token")]
+    CompoundNotSupported {},
+
+    #[error("No reward to compound")]
+    NoRewardToCompound {},
+
+    #[error("Invalid reward stream: {msg}")]
+    InvalidRewardStream { msg: String },
+
+    #[error("Reward stream {id} not found")]
+    RewardStreamNotFound { id: u64 },
+
+    #[error("Lockup tier {id} not found")]
+    LockupTierNotFound { id: u64 },
+
+    #[error("Invalid lockup tier {id}")]
+    InvalidLockupTier { id: u64 },
+
+    #[error("Requested unstake amount is larger than unlocked stake: {available}")]
+    MoreThanUnlockedAmount { available: Uint128 },
+
+    #[error("Instant unstake is disabled")]
+    InstantUnstakeDisabled {},
+
+    #[error("Stake amount is below the minimum stake: {min}")]
+    BelowMinStake { min: Uint128 },
+
+    #[error("Stake would exceed the maximum stake per address: {max}")]
+    AddressStakeLimitExceeded { max: Uint128 },
+
+    #[error("Stake would exceed the maximum total stake: {max}")]
+    TotalStakeLimitExceeded { max: Uint128 },
+
+    #[error("Staking is paused in emergency mode")]
+    EmergencyMode {},
+
+    #[error("Emergency mode is not active")]
+    NotInEmergencyMode {},
+
+    #[error("Unknown reply id {id}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("Hook is already registered")]
+    HookAlreadyRegistered {},
+
+    #[error("Hook is not registered")]
+    HookNotRegistered {},
+
+    #[error("Liquid staking is disabled")]
+    LiquidStakingDisabled {},
+
+    #[error("Liquid staking is closed while a reward stream pays another token")]
+    LiquidStreamNotCompoundable {},
+
+    #[error("Address is not allowed to stake")]
+    StakeNotAllowed {},
+
+    #[error("Stake transfers are disabled")]
+    TransferStakeDisabled {},
+
+    #[error("Can not transfer stake to yourself")]
+    SelfTransfer {},
+
+    #[error("Rewards go to the reward beneficiary, a recipient can not be set")]
+    RecipientWithBeneficiary {},
+
+    #[error("Penalty can not be more than 10000 basis points")]
+    InvalidPenalty {},
+
+    #[error("Epoch length must be more than zero")]
+    InvalidEpochLength {},
+
+    #[error("Emission duration must be more than zero")]
+    InvalidEmissionDuration {},
+
+    #[error("Not supported in the current reward mode")]
+    InvalidRewardMode {},
+
+    #[error("Can not migrate from contract {previous_contract}")]
+    CannotMigrate { previous_contract: String },
+
+    #[error("Can not migrate from newer version {previous_version}")]
+    CannotMigrateVersion { previous_version: String },
+
+    #[error("{0}")]
+    SemVer(String),
+
     #[error("{0}")]
     Overflow(#[from] OverflowError),
 
     #[error("{0}")]
     DivideByZeroError(#[from] DivideByZeroError),
 }
+
+impl From<semver::Error> for ContractError {
+    fn from(err: semver::Error) -> Self {
+        Self::SemVer(err.to_string())
+    }
+}
@@ -1,35 +1,296 @@
-use crate::state::StakeInfo;
-use cosmwasm_std::Uint128;
+use crate::msg::{RatioConfigResponse, RatioSourceQueryMsg};
+use crate::state::{EpochState, RewardMode, RewardStream, StakeInfo, State, StreamReward};
+use cosmwasm_std::{
+    Addr, BankMsg, CosmosMsg, Decimal, QuerierWrapper, StdResult, Timestamp, Uint128, Uint256,
+};
+use cw20::{Cw20Contract, Cw20ExecuteMsg, Denom};
+use cw_utils::{Duration, Expiration};
 
-// Output is amount of reward multiplied with 10000
-pub fn calculate_stake_reward(apr: Uint128, stake_info: StakeInfo, seconds: u128) -> Uint128 {
-    let stake_start_time = stake_info.stake_start_time;
-    let staked_amount = stake_info.stake_amount;
+pub const SECONDS_PER_YEAR: u128 = 31_536_000;
+pub const MAX_BPS: u64 = 10_000;
 
-    let each_second_reward = get_reward_for_each_second(apr.to_string());
+// dog-burn starts at 10 bDOG per burnt DOG, gdog at 1000 bDOG per gDOG plus one
+// for every step past the first
+const DOG_BURN_GENESIS_RATIO: u128 = 10;
+const GDOG_GENESIS_RATIO: u128 = 1000;
+
+// APR boost from the gdog/bdog ratio. The live contracts count in different units,
+// so each side of the stored ratio grows by how far its contract moved from its
+// genesis ratio. A side whose Config query fails keeps the stored value.
+pub fn get_multiplier(querier: &QuerierWrapper, state: &State) -> Decimal {
+    let bdog_growth = query_ratio_config(querier, state.dog_burn_address.as_ref())
+        .map(|config| Decimal::from_ratio(config.ratio, DOG_BURN_GENESIS_RATIO));
+    let gdog_growth = query_ratio_config(querier, state.gdog_address.as_ref()).map(|config| {
+        let ratio = match config.step {
+            Some(step) => Uint128::new(GDOG_GENESIS_RATIO - 1) + step,
+            None => config.ratio,
+        };
+        Decimal::from_ratio(ratio, GDOG_GENESIS_RATIO)
+    });
+    Decimal::from_ratio(state.gdog_ratio, state.bdog_ratio)
+        * gdog_growth.unwrap_or_else(Decimal::one)
+        / bdog_growth.unwrap_or_else(Decimal::one)
+}
+
+// Config of a dog-burn or gdog contract, none when it is not configured,
+// its query fails or it reports a zero ratio
+fn query_ratio_config(
+    querier: &QuerierWrapper,
+    source: Option<&Addr>,
+) -> Option<RatioConfigResponse> {
+    source
+        .and_then(|addr| {
+            querier
+                .query_wasm_smart::<RatioConfigResponse>(addr, &RatioSourceQueryMsg::Config {})
+                .ok()
+        })
+        .filter(|config| !config.ratio.is_zero())
+}
+
+// Accrues the stream's reward per staked token up to `seconds`.
+// In APR mode the rate is paid to every staked token, in fixed emission mode
+// the rate is the whole pool's emission per second shared by `total_staked`.
+pub fn update_stream(
+    stream: &mut RewardStream,
+    mode: &RewardMode,
+    multiplier: Decimal,
+    total_staked: Uint128,
+    seconds: u128,
+) -> StdResult<()> {
+    let now = Uint128::new(seconds);
+    let from = stream.last_update_time.max(stream.start_time);
+    let to = match stream.end_time {
+        Some(end_time) => end_time.min(now),
+        None => now,
+    };
+    if to > from {
+        match mode {
+            RewardMode::Apr {} => {
+                let elapsed = Decimal::from_ratio(to - from, SECONDS_PER_YEAR);
+                stream.reward_per_token = stream
+                    .reward_per_token
+                    .checked_add(stream.rate * multiplier * elapsed)?;
+            }
+            // nothing is distributed while nobody is staked
+            RewardMode::FixedEmission { .. } if !total_staked.is_zero() => {
+                // the emission in atomics overflows 128 bits for 18 decimal tokens
+                let emitted =
+                    Uint256::from(stream.rate.atomics()).checked_mul(Uint256::from(to - from))?;
+                let per_token = Uint128::try_from(emitted / Uint256::from(total_staked))?;
+                stream.reward_per_token = stream
+                    .reward_per_token
+                    .checked_add(Decimal::new(per_token))?;
+            }
+            RewardMode::FixedEmission { .. } => {}
+        }
+    }
+    stream.last_update_time = stream.last_update_time.max(now);
+    Ok(())
+}
+
+pub fn is_stream_active(stream: &RewardStream, now: Uint128) -> bool {
+    stream.start_time <= now && !matches!(stream.end_time, Some(end_time) if end_time <= now)
+}
+
+pub fn update_streams(
+    streams: &mut [RewardStream],
+    mode: &RewardMode,
+    multiplier: Decimal,
+    total_staked: Uint128,
+    seconds: u128,
+) -> StdResult<()> {
+    for stream in streams.iter_mut() {
+        update_stream(stream, mode, multiplier, total_staked, seconds)?;
+    }
+    Ok(())
+}
+
+// Moves epoch mode to the epoch `seconds` falls in. The epoch that ended is accrued
+// with the weight held through it, the ones after it with `total_weight` as nothing
+// changed in them. Returns the reward per token checkpoints of the epochs that started.
+pub fn roll_epochs(
+    epoch: &mut EpochState,
+    streams: &mut [RewardStream],
+    mode: &RewardMode,
+    multiplier: Decimal,
+    total_weight: Uint128,
+    seconds: u128,
+) -> StdResult<Vec<(u64, Vec<Decimal>)>> {
+    let current = epoch.epoch_at(seconds);
+    let mut checkpoints = vec![];
+    if current > epoch.epoch {
+        let next = epoch.epoch + 1;
+        let start = epoch.epoch_start(next).u128();
+        update_streams(streams, mode, multiplier, epoch.eligible_weight, start)?;
+        checkpoints.push((next, reward_per_token(streams)));
+        if current > next {
+            let start = epoch.epoch_start(current).u128();
+            update_streams(streams, mode, multiplier, total_weight, start)?;
+            checkpoints.push((current, reward_per_token(streams)));
+        }
+        epoch.epoch = current;
+        epoch.eligible_weight = total_weight;
+    }
+    Ok(checkpoints)
+}
+
+fn reward_per_token(streams: &[RewardStream]) -> Vec<Decimal> {
+    streams
+        .iter()
+        .map(|stream| stream.reward_per_token)
+        .collect()
+}
+
+// Tokens a running stream pays out per year at the current rate
+pub fn yearly_emission(
+    stream: &RewardStream,
+    mode: &RewardMode,
+    multiplier: Decimal,
+    total_staked: Uint128,
+) -> Uint128 {
+    match mode {
+        RewardMode::Apr {} => total_staked * (stream.rate * multiplier),
+        RewardMode::FixedEmission { .. } => Uint128::new(SECONDS_PER_YEAR) * stream.rate,
+    }
+}
+
+// Reward weight of a stake, locked positions count with their boost until they
+// are released at their unlock time
+pub fn stake_weight(stake_info: &StakeInfo) -> Uint128 {
+    stake_info
+        .locked_positions
+        .iter()
+        .fold(stake_info.stake_amount, |weight, position| {
+            weight + position.amount * position.boost - position.amount
+        })
+}
+
+// When the unstaking amount can be claimed, counted from the last StartUnstake
+pub fn unbonding_release(state: &State, stake_info: &StakeInfo) -> Expiration {
+    match state.unstaking_duration {
+        Duration::Height(blocks) => {
+            Expiration::AtHeight(stake_info.unstaking_start_height + blocks)
+        }
+        Duration::Time(seconds) => {
+            Expiration::AtTime(stake_info.unstaking_start_time.plus_seconds(seconds))
+        }
+    }
+}
+
+// Stake that no lock holds back from unstaking
+pub fn unlocked_amount(stake_info: &StakeInfo) -> Uint128 {
+    let locked: Uint128 = stake_info
+        .locked_positions
+        .iter()
+        .map(|position| position.amount)
+        .sum();
+    stake_info.stake_amount - locked
+}
+
+// Part of the settled interval `from..to` a lock ending at `unlock_time` was still boosted
+fn boosted_fraction(unlock_time: Uint128, from: Uint128, to: Uint128) -> Decimal {
+    if unlock_time >= to {
+        Decimal::one()
+    } else if unlock_time <= from {
+        Decimal::zero()
+    } else {
+        Decimal::from_ratio(unlock_time - from, to - from)
+    }
+}
+
+// Moves what the stake earned on every stream into its pending reward and
+// releases locked positions that ran out. Streams must be updated to `seconds` first.
+pub fn settle_reward(streams: &[RewardStream], stake_info: &mut StakeInfo, seconds: u128) {
     let now = Uint128::new(seconds);
-    let mut reward = Uint128::zero();
+    let last_settlement = Uint128::new(stake_info.reward_start_time.seconds() as u128);
+    for stream in streams {
+        let index = match stake_info
+            .rewards
+            .iter()
+            .position(|r| r.stream_id == stream.id)
+        {
+            Some(index) => index,
+            // stream was added after the last settlement, it started from zero
+            None => {
+                stake_info.rewards.push(StreamReward {
+                    stream_id: stream.id,
+                    pending: Uint128::zero(),
+                    reward_per_token_paid: Decimal::zero(),
+                });
+                stake_info.rewards.len() - 1
+            }
+        };
+        let delta = stream.reward_per_token - stake_info.rewards[index].reward_per_token_paid;
+        let mut earned = stake_info.stake_amount * delta;
+        // the boost is only paid up to the unlock time, assuming an even accrual
+        // over the interval since the last settlement
+        for position in &stake_info.locked_positions {
+            let extra = position.amount * position.boost - position.amount;
+            let unlock_time = Uint128::new(position.unlock_time.seconds() as u128);
+            let fraction = boosted_fraction(unlock_time, last_settlement, now);
+            earned += extra * (delta * fraction);
+        }
+        let reward = &mut stake_info.rewards[index];
+        reward.pending += earned;
+        reward.reward_per_token_paid = stream.reward_per_token;
+    }
+    stake_info
+        .locked_positions
+        .retain(|position| position.unlock_time.seconds() as u128 > seconds);
+    stake_info.reward_start_time = Timestamp::from_seconds(seconds as u64);
+}
+
+// Rewards can only be restaked when they are paid in the staked token
+pub fn is_compoundable(state: &State, stream: &RewardStream) -> bool {
+    stream.token == staked_token(state)
+}
 
-    if staked_amount > Uint128::zero() {
-        let second_difference_from_start = now - stake_start_time;
-        let one_token_reward = second_difference_from_start * each_second_reward;
-        reward = one_token_reward * staked_amount;
+// The token stakers deposit, native when stake_denom is set
+pub fn staked_token(state: &State) -> Denom {
+    match &state.stake_denom {
+        Some(denom) => Denom::Native(denom.clone()),
+        None => Denom::Cw20(state.token_address.clone()),
     }
-    reward
 }
 
-pub fn get_reward_for_each_second(apr: String) -> Uint128 {
-    // find reward for each passing second
-    let decimal = Uint128::new(100000);
-    let for_decimal_values = Uint128::new(100);
-    let mut annual = Uint128::new(apr.parse::<u128>().unwrap());
-    annual = annual * decimal * for_decimal_values;
+pub fn can_compound(state: &State, streams: &[RewardStream]) -> bool {
+    streams.iter().any(|stream| is_compoundable(state, stream))
+}
 
-    let days = Uint128::new(365);
-    let daily_reward = annual / days;
+// Splits an instant unstake into (penalty, payout), the penalty is rounded down
+pub fn instant_unstake_penalty(amount: Uint128, bps: u64) -> (Uint128, Uint128) {
+    let penalty = amount.multiply_ratio(bps, MAX_BPS);
+    (penalty, amount - penalty)
+}
 
-    let seconds = Uint128::new(86400);
-    let second_reward = daily_reward / seconds;
+// Storage key of a reward token
+pub fn denom_key(token: &Denom) -> String {
+    match token {
+        Denom::Native(denom) => denom.clone(),
+        Denom::Cw20(address) => address.to_string(),
+    }
+}
 
-    second_reward
+pub fn transfer_msg(token: &Denom, recipient: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
+    match token {
+        Denom::Cw20(address) => Cw20Contract(address.clone()).call(Cw20ExecuteMsg::Transfer {
+            recipient: recipient.to_string(),
+            amount,
+        }),
+        Denom::Native(denom) => Ok(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![cosmwasm_std::coin(amount.u128(), denom)],
+        }
+        .into()),
+    }
+}
+
+pub fn burn_msg(token: &Denom, amount: Uint128) -> StdResult<CosmosMsg> {
+    match token {
+        Denom::Cw20(address) => Cw20Contract(address.clone()).call(Cw20ExecuteMsg::Burn { amount }),
+        Denom::Native(denom) => Ok(BankMsg::Burn {
+            amount: vec![cosmwasm_std::coin(amount.u128(), denom)],
+        }
+        .into()),
+    }
 }
@@ -0,0 +1,97 @@
+// Storage layout of cw20-stake v0.1.0, only read by the migration
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Decimal, StdError, StdResult, Timestamp, Uint128};
+use cw_storage_plus::{Item, Map};
+use std::str::FromStr;
+
+use crate::helper::SECONDS_PER_YEAR;
+use crate::state::StreamReward;
+
+// v0.1.0 stored rewards scaled by 10^9: its per second rate was apr * 10^7 / year
+pub const LEGACY_REWARD_SCALE: u128 = 1_000_000_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct State {
+    pub allowed_operators: Vec<Addr>,
+    pub unstaking_duration: Uint128,
+    // yearly reward in percent, "20" is 20%
+    pub apr: String,
+    pub bdog_ratio: Uint128,
+    pub gdog_ratio: Uint128,
+    pub token_address: Addr,
+    pub token_source: Addr,
+    pub reward_token_address: Addr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StakeInfo {
+    pub owner: Addr,
+    pub stake_amount: Uint128,
+    pub unstaking_amount: Uint128,
+    pub reward_amount: Uint128,
+    pub apr: String,
+    pub stake_start_time: Uint128,
+    pub reward_start_time: Uint128,
+    pub unstaking_start_time: Uint128,
+    pub reward_end_time: Uint128,
+    pub unstaking_process: bool,
+    pub unstake_end_time: Uint128,
+}
+
+pub const STATE: Item<State> = Item::new("state");
+pub const STAKE_LIST: Map<Addr, StakeInfo> = Map::new("stake_list");
+// never written by v0.1.0, reward totals are kept per stream in the claim stats now
+pub const REWARD_TOTAL: Item<Uint128> = Item::new("reward_total");
+
+// The percent string of v0.1.0 as a rate, "20" becomes 0.2
+pub fn apr_rate(apr: &str) -> StdResult<Decimal> {
+    let percent = Decimal::from_str(apr)
+        .map_err(|_| StdError::generic_err(format!("Invalid legacy apr: {}", apr)))?;
+    Ok(percent * Decimal::percent(1))
+}
+
+impl State {
+    // The apr rate v0.1.0 paid, boosted by gdog_ratio / bdog_ratio rounded down
+    pub fn yearly_rate(&self) -> StdResult<Decimal> {
+        let multiplier = self.gdog_ratio.checked_div(self.bdog_ratio)?;
+        Ok(apr_rate(&self.apr)? * Decimal::from_ratio(multiplier, 1u128))
+    }
+}
+
+impl StakeInfo {
+    // What v0.1.0 owed: the stored reward plus what accrued since the stake
+    // started at `yearly_rate`, the apr with the multiplier applied
+    pub fn owed_reward(&self, yearly_rate: Decimal, now: u128) -> Uint128 {
+        let stored = self.reward_amount / Uint128::new(LEGACY_REWARD_SCALE);
+        let start = self.stake_start_time.u128();
+        if self.stake_amount.is_zero() || start == 0 || now <= start {
+            return stored;
+        }
+        stored + (self.stake_amount * yearly_rate).multiply_ratio(now - start, SECONDS_PER_YEAR)
+    }
+
+    // The position in the current layout with everything owed settled into
+    // the pending reward of stream 0, which starts at `now`
+    pub fn upgrade(self, yearly_rate: Decimal, now: u128) -> crate::state::StakeInfo {
+        let pending = self.owed_reward(yearly_rate, now);
+        crate::state::StakeInfo {
+            owner: self.owner,
+            stake_amount: self.stake_amount,
+            unstaking_amount: self.unstaking_amount,
+            rewards: vec![StreamReward {
+                stream_id: 0,
+                pending,
+                reward_per_token_paid: Decimal::zero(),
+            }],
+            stake_start_time: Timestamp::from_seconds(self.stake_start_time.u128() as u64),
+            reward_start_time: Timestamp::from_seconds(now as u64),
+            unstaking_start_time: Timestamp::from_seconds(self.unstaking_start_time.u128() as u64),
+            unstaking_start_height: 0,
+            unstaking_process: self.unstaking_process,
+            auto_compound: false,
+            locked_positions: vec![],
+        }
+    }
+}
@@ -1,8 +1,14 @@
 pub mod contract;
 mod error;
 pub mod helper;
+mod legacy;
 pub mod msg;
+#[cfg(test)]
+mod multitest;
+#[cfg(test)]
+mod proptests;
 pub mod state;
+#[cfg(test)]
 mod tests;
 
 pub use crate::error::ContractError;
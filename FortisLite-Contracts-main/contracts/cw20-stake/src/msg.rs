@@ -1,47 +1,153 @@
-use crate::state::StakeInfo;
+use crate::state::{
+    ClaimRecord, ClaimStats, LockupTier, PenaltyConfig, RewardMode, RewardStream, StakeGate,
+    StakeInfo, StakeLimits, StreamClaimed,
+};
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Uint128};
-use cw20::Cw20ReceiveMsg;
+use cosmwasm_std::{Addr, Decimal, Timestamp, Uint128};
+use cw20::{Cw20ReceiveMsg, Denom};
+use cw_utils::Duration;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 #[cw_serde]
 pub struct InstantiateMsg {
     pub allowed_operators: Vec<Addr>,
-    pub unstaking_duration: Uint128,
-    pub apr: String,
+    pub unstaking_duration: Duration,
     pub bdog_ratio: Uint128,
     pub gdog_ratio: Uint128,
+    pub dog_burn_address: Option<Addr>,
+    pub gdog_address: Option<Addr>,
     pub token_address: Addr,
+    pub stake_denom: Option<String>,
     pub token_source: Addr,
-    pub reward_token_address: Addr,
+    pub reward_mode: RewardMode,
+    pub reward_streams: Vec<RewardStreamMsg>,
+    pub lockup_tiers: Vec<LockupTier>,
+    pub instant_unstake_penalty: Option<PenaltyConfig>,
+    pub transfers_enabled: bool,
+    pub stake_limits: StakeLimits,
+    pub receipt_token: Option<Addr>,
+    // rewards are settled per epoch of this many seconds instead of per second
+    pub epoch_length: Option<Uint128>,
+    pub stake_gate: StakeGate,
 }
 
+#[cw_serde]
+pub struct MigrateMsg {}
+
+#[cw_serde]
+pub struct RewardStreamMsg {
+    pub token: Denom,
+    pub rate: Decimal,
+    pub start_time: Option<Uint128>,
+    pub end_time: Option<Uint128>,
+}
+
+// messages are only deserialized once per call, boxing EditState is not worth it
+#[allow(clippy::large_enum_variant)]
 #[cw_serde]
 pub enum ExecuteMsg {
     Receive(Cw20ReceiveMsg),
+    // stakes the attached funds when staking a native denom
+    Stake {},
+    StakeLocked {
+        tier_id: u64,
+    },
+    StakeLiquid {},
     StartUnstake {
         amount: Uint128,
     },
     ClaimUnstaked {
         amount: Uint128,
     },
+    CancelUnstake {
+        amount: Uint128,
+    },
+    InstantUnstake {
+        amount: Uint128,
+    },
+    // returns staked and unstaking tokens right away in emergency mode,
+    // pending rewards are given up
+    EmergencyWithdraw {},
+    // pays at most `amount` of every reward stream, everything when not set,
+    // to the reward beneficiary when one is set, else to `recipient` or the sender.
+    // Setting `recipient` while a beneficiary is set is an error.
     ClaimReward {
+        amount: Option<Uint128>,
+        recipient: Option<String>,
+    },
+    // sends the sender's rewards to `addr` from now on, back to the sender when not set
+    SetRewardBeneficiary {
+        addr: Option<String>,
+    },
+    // moves unlocked stake to another address
+    TransferStake {
+        recipient: String,
         amount: Uint128,
     },
+    Compound {},
+    SetAutoCompound {
+        enabled: bool,
+    },
     EditState {
         allowed_operators: Option<Vec<Addr>>,
         token_address: Option<Addr>,
-        unstaking_duration: Option<Uint128>,
-        apr: Option<String>,
+        unstaking_duration: Option<Duration>,
         bdog_ratio: Option<Uint128>,
         gdog_ratio: Option<Uint128>,
+        dog_burn_address: Option<Addr>,
+        gdog_address: Option<Addr>,
         token_source: Option<Addr>,
-        reward_token_address: Option<Addr>,
+        lockup_tiers: Option<Vec<LockupTier>>,
+        instant_unstake_penalty: Option<PenaltyConfig>,
+        transfers_enabled: Option<bool>,
+        // replaces every limit, unset ones are lifted
+        stake_limits: Option<StakeLimits>,
+        receipt_token: Option<Addr>,
+        emergency_mode: Option<bool>,
+        stake_gate: Option<StakeGate>,
+    },
+    // operators manage who can stake in allowlist gating
+    UpdateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    AddRewardStream {
+        token: Denom,
+        rate: Decimal,
+        start_time: Option<Uint128>,
+        end_time: Option<Uint128>,
+    },
+    UpdateRewardStream {
+        id: u64,
+        rate: Option<Decimal>,
+        end_time: Option<Uint128>,
+    },
+    // contracts that receive a StakeChangedHookMsg on every stake change
+    AddHook {
+        addr: String,
+    },
+    RemoveHook {
+        addr: String,
+    },
+    FundRewards {},
+    NotifyRewardAmount {
+        stream_id: u64,
     },
 }
 
 #[cw_serde]
 pub enum ReceiveMsg {
     Stake {},
+    StakeLocked { tier_id: u64 },
+    // credits the stake to `recipient` instead of the sender
+    StakeFor { recipient: String },
+    // stakes into the liquid pool and mints receipt tokens to the sender
+    StakeLiquid {},
+    // sent with receipt tokens, burns them and starts unstaking their value
+    UnstakeLiquid {},
+    FundRewards {},
+    NotifyRewardAmount { stream_id: u64 },
 }
 
 #[cw_serde]
@@ -51,8 +157,52 @@ pub enum QueryMsg {
     GetUserReward { addr: Addr },
     #[returns(GetStakeResponse)]
     GetUserStakeInfo { addr: Addr },
+    #[returns(UserPositionsResponse)]
+    GetUserPositions { addr: Addr },
     #[returns(GetStateResponse)]
     GetState {},
+    #[returns(VotingPowerAtHeightResponse)]
+    VotingPowerAtHeight {
+        address: String,
+        height: Option<u64>,
+    },
+    #[returns(TotalPowerAtHeightResponse)]
+    TotalPowerAtHeight { height: Option<u64> },
+    #[returns(SimulateInstantUnstakeResponse)]
+    SimulateInstantUnstake { amount: Uint128 },
+    #[returns(StakeListResponse)]
+    TopStakers {
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    },
+    #[returns(PoolInfoResponse)]
+    PoolInfo {},
+    #[returns(EpochResponse)]
+    Epoch {},
+    #[returns(HooksResponse)]
+    Hooks {},
+    // where the rewards of `addr` are paid, `addr` itself without a beneficiary
+    #[returns(RewardBeneficiaryResponse)]
+    RewardBeneficiary { addr: Addr },
+    #[returns(AllowlistResponse)]
+    Allowlist {
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    },
+    // whether the address passes the stake gate
+    #[returns(CanStakeResponse)]
+    CanStake { addr: Addr },
+    #[returns(RewardHistoryResponse)]
+    RewardHistory {
+        addr: Addr,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    #[returns(StatsResponse)]
+    Stats {
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    },
     #[returns(StakeListResponse)]
     RangeStakeList {
         start_after: Option<Addr>,
@@ -61,20 +211,177 @@ pub enum QueryMsg {
 }
 
 #[cw_serde]
-pub struct GetUserRewardResponse {
+pub struct RewardAmount {
+    pub stream_id: u64,
+    pub token: Denom,
     pub amount: Uint128,
 }
 
+#[cw_serde]
+pub struct GetUserRewardResponse {
+    pub rewards: Vec<RewardAmount>,
+}
+
 #[cw_serde]
 pub struct GetStateResponse {
     pub allowed_operators: Vec<Addr>,
     pub token_address: Addr,
-    pub unstaking_duration: Uint128,
-    pub apr: String,
+    pub stake_denom: Option<String>,
+    pub unstaking_duration: Duration,
     pub token_source: Addr,
+    pub bdog_ratio: Uint128,
+    pub gdog_ratio: Uint128,
+    pub dog_burn_address: Option<Addr>,
+    pub gdog_address: Option<Addr>,
+    // APR boost currently applied to reward streams
+    pub multiplier: Decimal,
     pub total_staked: Uint128,
-    pub total_reward: Uint128,
-    pub reward_token_address: Addr,
+    // rewards paid out so far, per stream as streams pay different tokens
+    pub rewards_claimed: Vec<StreamClaimed>,
+    pub reward_mode: RewardMode,
+    pub lockup_tiers: Vec<LockupTier>,
+    pub instant_unstake_penalty: Option<PenaltyConfig>,
+    pub transfers_enabled: bool,
+    pub stake_limits: StakeLimits,
+    pub receipt_token: Option<Addr>,
+    pub receipt_supply: Uint128,
+    pub emergency_mode: bool,
+    pub stake_gate: StakeGate,
+    // current rate and period end of every stream
+    pub reward_streams: Vec<RewardStream>,
+    pub reward_reserves: Vec<RewardReserveResponse>,
+}
+
+#[cw_serde]
+pub struct RewardReserveResponse {
+    pub token: Denom,
+    pub amount: Uint128,
+    // None when nothing is being emitted
+    pub runway_seconds: Option<Uint128>,
+}
+
+#[cw_serde]
+pub struct PositionResponse {
+    pub tier_id: u64,
+    pub amount: Uint128,
+    pub boost: Decimal,
+    pub unlock_time: Timestamp,
+    pub unlocked: bool,
+}
+
+#[cw_serde]
+pub struct UserPositionsResponse {
+    // stake that can start unstaking
+    pub unlocked_amount: Uint128,
+    pub positions: Vec<PositionResponse>,
+}
+
+#[cw_serde]
+pub struct VotingPowerAtHeightResponse {
+    pub power: Uint128,
+    pub height: u64,
+}
+
+#[cw_serde]
+pub struct TotalPowerAtHeightResponse {
+    pub power: Uint128,
+    pub height: u64,
+}
+
+#[cw_serde]
+pub struct SimulateInstantUnstakeResponse {
+    pub amount: Uint128,
+    pub penalty: Uint128,
+    pub payout: Uint128,
+}
+
+#[cw_serde]
+pub struct StreamAprResponse {
+    pub stream_id: u64,
+    pub token: Denom,
+    // yearly reward of one unboosted staked token
+    pub apr: Decimal,
+}
+
+#[cw_serde]
+pub struct EpochResponse {
+    pub epoch: u64,
+    pub start: Uint128,
+    pub end: Uint128,
+    // weight that earns rewards for the whole epoch
+    pub eligible_weight: Uint128,
+}
+
+#[cw_serde]
+pub struct PoolInfoResponse {
+    pub stakers: u64,
+    pub total_staked: Uint128,
+    pub total_unbonding: Uint128,
+    pub staked_token: Denom,
+    // staked and unbonding tokens held by the contract, in the staked token
+    pub tvl: Uint128,
+    pub stream_aprs: Vec<StreamAprResponse>,
+}
+
+#[cw_serde]
+pub struct ClaimRecordResponse {
+    pub id: u64,
+    pub record: ClaimRecord,
+}
+
+#[cw_serde]
+pub struct RewardHistoryResponse {
+    pub records: Vec<ClaimRecordResponse>,
+}
+
+#[cw_serde]
+pub struct UserStatsResponse {
+    pub addr: Addr,
+    pub stats: ClaimStats,
+}
+
+#[cw_serde]
+pub struct StatsResponse {
+    pub total: ClaimStats,
+    // lifetime stats of every user, paginated
+    pub users: Vec<UserStatsResponse>,
+}
+
+#[cw_serde]
+pub struct HooksResponse {
+    pub hooks: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct RewardBeneficiaryResponse {
+    pub beneficiary: Addr,
+}
+
+#[cw_serde]
+pub struct AllowlistResponse {
+    pub addresses: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct CanStakeResponse {
+    pub can_stake: bool,
+}
+
+// Sent to every registered hook when the staked amount of `addr` changes and on
+// every claim of rewards or unbonded tokens, where `old` and `new` may be equal.
+// EmergencyWithdraw sends it with its failure ignored, so the exit never
+// depends on a hook succeeding.
+#[cw_serde]
+pub struct StakeChangedHookMsg {
+    pub addr: Addr,
+    pub old: Uint128,
+    pub new: Uint128,
+}
+
+// Execute message hook contracts have to accept
+#[cw_serde]
+pub enum StakeChangedExecuteMsg {
+    StakeChangeHook(StakeChangedHookMsg),
 }
 
 #[cw_serde]
@@ -86,3 +393,29 @@ pub struct StakeListResponse {
 pub struct GetStakeResponse {
     pub info: StakeInfo,
 }
+
+// Config query of the dog-burn and gdog contracts
+#[cw_serde]
+pub enum RatioSourceQueryMsg {
+    Config {},
+}
+
+// Membership query of a gating contract like dog-drop
+#[cw_serde]
+pub enum MembershipQueryMsg {
+    IsMember { address: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IsMemberResponse {
+    pub is_member: bool,
+}
+
+// Part of the dog-burn and gdog Config responses read by this contract.
+// dog-burn has no step, gdog's ratio is 999 plus its step.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RatioConfigResponse {
+    pub ratio: Uint128,
+    #[serde(default)]
+    pub step: Option<Uint128>,
+}
@@ -0,0 +1,226 @@
+use cosmwasm_std::{to_binary, Addr, Decimal, Empty, Uint128};
+use cw20::{Cw20Coin, Cw20ExecuteMsg, Denom};
+use cw_multi_test::{App, AppResponse, Contract, ContractWrapper, Executor};
+use cw_utils::Duration;
+
+use crate::msg::{
+    ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg, RewardStreamMsg, TotalPowerAtHeightResponse,
+    VotingPowerAtHeightResponse,
+};
+use crate::state::{RewardMode, StakeGate};
+
+const OPERATOR: &str = "operator";
+const ALICE: &str = "alice";
+const BOB: &str = "bob";
+
+pub(crate) fn contract_stake() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(
+        crate::contract::execute,
+        crate::contract::instantiate,
+        crate::contract::query,
+    ))
+}
+
+pub(crate) fn contract_cw20() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    ))
+}
+
+struct Suite {
+    app: App,
+    token: Addr,
+    stake: Addr,
+}
+
+impl Suite {
+    fn new(balances: &[(&str, u128)]) -> Self {
+        let mut app = App::default();
+        let cw20_id = app.store_code(contract_cw20());
+        let stake_id = app.store_code(contract_stake());
+
+        let token = app
+            .instantiate_contract(
+                cw20_id,
+                Addr::unchecked(OPERATOR),
+                &cw20_base::msg::InstantiateMsg {
+                    name: "Ash".to_string(),
+                    symbol: "ASH".to_string(),
+                    decimals: 6,
+                    initial_balances: balances
+                        .iter()
+                        .map(|(address, amount)| Cw20Coin {
+                            address: address.to_string(),
+                            amount: Uint128::new(*amount),
+                        })
+                        .collect(),
+                    mint: None,
+                    marketing: None,
+                },
+                &[],
+                "ash",
+                None,
+            )
+            .unwrap();
+        let stake = app
+            .instantiate_contract(
+                stake_id,
+                Addr::unchecked(OPERATOR),
+                &InstantiateMsg {
+                    allowed_operators: vec![Addr::unchecked(OPERATOR)],
+                    unstaking_duration: Duration::Time(100),
+                    bdog_ratio: Uint128::new(1),
+                    gdog_ratio: Uint128::new(1),
+                    dog_burn_address: None,
+                    gdog_address: None,
+                    token_address: token.clone(),
+                    stake_denom: None,
+                    token_source: Addr::unchecked(OPERATOR),
+                    reward_mode: RewardMode::Apr {},
+                    reward_streams: vec![RewardStreamMsg {
+                        token: Denom::Cw20(token.clone()),
+                        rate: Decimal::percent(20),
+                        start_time: None,
+                        end_time: None,
+                    }],
+                    lockup_tiers: vec![],
+                    instant_unstake_penalty: None,
+                    transfers_enabled: false,
+                    stake_limits: Default::default(),
+                    receipt_token: None,
+                    epoch_length: None,
+                    stake_gate: StakeGate::Open {},
+                },
+                &[],
+                "cw20-stake",
+                None,
+            )
+            .unwrap();
+        Suite { app, token, stake }
+    }
+
+    fn next_block(&mut self) {
+        self.app.update_block(|block| {
+            block.height += 1;
+            block.time = block.time.plus_seconds(5);
+        });
+    }
+
+    fn height(&self) -> u64 {
+        self.app.block_info().height
+    }
+
+    fn stake(&mut self, sender: &str, amount: u128) -> AppResponse {
+        self.app
+            .execute_contract(
+                Addr::unchecked(sender),
+                self.token.clone(),
+                &Cw20ExecuteMsg::Send {
+                    contract: self.stake.to_string(),
+                    amount: Uint128::new(amount),
+                    msg: to_binary(&ReceiveMsg::Stake {}).unwrap(),
+                },
+                &[],
+            )
+            .unwrap()
+    }
+
+    fn execute(&mut self, sender: &str, msg: ExecuteMsg) -> anyhow::Result<AppResponse> {
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.stake.clone(), &msg, &[])
+    }
+
+    fn voting_power(&self, address: &str, height: Option<u64>) -> Uint128 {
+        let res: VotingPowerAtHeightResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                &self.stake,
+                &QueryMsg::VotingPowerAtHeight {
+                    address: address.to_string(),
+                    height,
+                },
+            )
+            .unwrap();
+        res.power
+    }
+
+    fn total_power(&self, height: Option<u64>) -> Uint128 {
+        let res: TotalPowerAtHeightResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(&self.stake, &QueryMsg::TotalPowerAtHeight { height })
+            .unwrap();
+        res.power
+    }
+}
+
+#[test]
+fn voting_power_follows_stake_and_unstake() {
+    let mut suite = Suite::new(&[(ALICE, 1000), (BOB, 1000)]);
+    let start = suite.height();
+
+    suite.stake(ALICE, 100);
+    suite.next_block();
+    let after_alice = suite.height();
+    suite.stake(BOB, 300);
+    suite.stake(ALICE, 50);
+    suite.next_block();
+    let after_bob = suite.height();
+    suite
+        .execute(
+            ALICE,
+            ExecuteMsg::StartUnstake {
+                amount: Uint128::new(120),
+            },
+        )
+        .unwrap();
+    suite.next_block();
+    let after_unstake = suite.height();
+
+    // power at a height is the balance at the start of that block
+    assert_eq!(suite.voting_power(ALICE, Some(start)), Uint128::zero());
+    assert_eq!(suite.total_power(Some(start)), Uint128::zero());
+
+    assert_eq!(
+        suite.voting_power(ALICE, Some(after_alice)),
+        Uint128::new(100)
+    );
+    assert_eq!(suite.voting_power(BOB, Some(after_alice)), Uint128::zero());
+    assert_eq!(suite.total_power(Some(after_alice)), Uint128::new(100));
+
+    assert_eq!(
+        suite.voting_power(ALICE, Some(after_bob)),
+        Uint128::new(150)
+    );
+    assert_eq!(suite.voting_power(BOB, Some(after_bob)), Uint128::new(300));
+    assert_eq!(suite.total_power(Some(after_bob)), Uint128::new(450));
+
+    // unbonding tokens carry no voting power
+    assert_eq!(
+        suite.voting_power(ALICE, Some(after_unstake)),
+        Uint128::new(30)
+    );
+    assert_eq!(suite.total_power(Some(after_unstake)), Uint128::new(330));
+    assert_eq!(suite.voting_power(ALICE, None), Uint128::new(30));
+    assert_eq!(suite.total_power(None), Uint128::new(330));
+
+    // restaking the unbonding tokens restores the power
+    suite
+        .execute(
+            ALICE,
+            ExecuteMsg::CancelUnstake {
+                amount: Uint128::new(120),
+            },
+        )
+        .unwrap();
+    suite.next_block();
+    assert_eq!(suite.voting_power(ALICE, None), Uint128::new(150));
+    assert_eq!(suite.total_power(None), Uint128::new(450));
+    assert_eq!(
+        suite.voting_power(ALICE, Some(after_unstake)),
+        Uint128::new(30)
+    );
+}
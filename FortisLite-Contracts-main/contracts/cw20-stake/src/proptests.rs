@@ -0,0 +1,318 @@
+// Reward math invariants under random sequences of stakes, unstakes, claims and
+// APR edits, run against a cw-multi-test deployment with cw20-base tokens
+use cosmwasm_std::{to_binary, Addr, Decimal, Uint128};
+use cw20::{BalanceResponse, Cw20Coin, Cw20ExecuteMsg, Cw20QueryMsg, Denom};
+use cw_multi_test::{App, Executor};
+use cw_utils::Duration;
+use proptest::prelude::*;
+
+use crate::helper::SECONDS_PER_YEAR;
+use crate::msg::{
+    ExecuteMsg, GetStakeResponse, GetStateResponse, GetUserRewardResponse, InstantiateMsg,
+    QueryMsg, ReceiveMsg, RewardStreamMsg,
+};
+use crate::multitest::{contract_cw20, contract_stake};
+use crate::state::{RewardMode, StakeGate};
+
+const OPERATOR: &str = "operator";
+const USERS: [&str; 3] = ["alice", "bob", "carol"];
+const BALANCE: u128 = 1_000_000_000_000;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Stake { user: usize, amount: u128 },
+    Unstake { user: usize, amount: u128 },
+    Claim { user: usize },
+    SetApr { percent: u64 },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..USERS.len(), 1..1_000_000_000u128)
+            .prop_map(|(user, amount)| Op::Stake { user, amount }),
+        (0..USERS.len(), 1..1_000_000_000u128)
+            .prop_map(|(user, amount)| Op::Unstake { user, amount }),
+        (0..USERS.len()).prop_map(|user| Op::Claim { user }),
+        (0..=100u64).prop_map(|percent| Op::SetApr { percent }),
+    ]
+}
+
+// What the contract should have paid, tracked outside of it
+#[derive(Default)]
+struct Staker {
+    staked: u128,
+    // stake times APR percent times seconds, summed
+    reward_integral: u128,
+    // settlements that could each round the reward down by one token
+    settlements: u128,
+}
+
+struct Suite {
+    app: App,
+    token: Addr,
+    reward_token: Addr,
+    stake: Addr,
+}
+
+impl Suite {
+    fn new(apr: u64) -> Self {
+        let mut app = App::default();
+        let cw20_id = app.store_code(contract_cw20());
+        let stake_id = app.store_code(contract_stake());
+
+        let mut cw20 = |symbol: &str, holders: &[&str]| {
+            app.instantiate_contract(
+                cw20_id,
+                Addr::unchecked(OPERATOR),
+                &cw20_base::msg::InstantiateMsg {
+                    name: symbol.to_string(),
+                    symbol: symbol.to_string(),
+                    decimals: 6,
+                    initial_balances: holders
+                        .iter()
+                        .map(|address| Cw20Coin {
+                            address: address.to_string(),
+                            amount: Uint128::new(BALANCE),
+                        })
+                        .collect(),
+                    mint: None,
+                    marketing: None,
+                },
+                &[],
+                symbol,
+                None,
+            )
+            .unwrap()
+        };
+        let token = cw20("ASH", &USERS);
+        let reward_token = cw20("DOG", &[OPERATOR]);
+
+        let stake = app
+            .instantiate_contract(
+                stake_id,
+                Addr::unchecked(OPERATOR),
+                &InstantiateMsg {
+                    allowed_operators: vec![Addr::unchecked(OPERATOR)],
+                    unstaking_duration: Duration::Time(100),
+                    bdog_ratio: Uint128::new(1),
+                    gdog_ratio: Uint128::new(1),
+                    dog_burn_address: None,
+                    gdog_address: None,
+                    token_address: token.clone(),
+                    stake_denom: None,
+                    token_source: Addr::unchecked(OPERATOR),
+                    reward_mode: RewardMode::Apr {},
+                    reward_streams: vec![RewardStreamMsg {
+                        token: Denom::Cw20(reward_token.clone()),
+                        rate: Decimal::percent(apr),
+                        start_time: None,
+                        end_time: None,
+                    }],
+                    lockup_tiers: vec![],
+                    instant_unstake_penalty: None,
+                    transfers_enabled: false,
+                    stake_limits: Default::default(),
+                    receipt_token: None,
+                    epoch_length: None,
+                    stake_gate: StakeGate::Open {},
+                },
+                &[],
+                "cw20-stake",
+                None,
+            )
+            .unwrap();
+
+        // enough reserve that no claim is ever cut short
+        app.execute_contract(
+            Addr::unchecked(OPERATOR),
+            reward_token.clone(),
+            &Cw20ExecuteMsg::Send {
+                contract: stake.to_string(),
+                amount: Uint128::new(BALANCE),
+                msg: to_binary(&ReceiveMsg::FundRewards {}).unwrap(),
+            },
+            &[],
+        )
+        .unwrap();
+
+        Suite {
+            app,
+            token,
+            reward_token,
+            stake,
+        }
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        self.app.update_block(|block| {
+            block.height += 1;
+            block.time = block.time.plus_seconds(seconds);
+        });
+    }
+
+    fn stake(&mut self, user: &str, amount: u128) {
+        self.app
+            .execute_contract(
+                Addr::unchecked(user),
+                self.token.clone(),
+                &Cw20ExecuteMsg::Send {
+                    contract: self.stake.to_string(),
+                    amount: Uint128::new(amount),
+                    msg: to_binary(&ReceiveMsg::Stake {}).unwrap(),
+                },
+                &[],
+            )
+            .unwrap();
+    }
+
+    fn execute(&mut self, sender: &str, msg: ExecuteMsg) {
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.stake.clone(), &msg, &[])
+            .unwrap();
+    }
+
+    // pending reward, zero for addresses that never staked
+    fn pending(&self, user: &str) -> u128 {
+        self.app
+            .wrap()
+            .query_wasm_smart::<GetUserRewardResponse>(
+                &self.stake,
+                &QueryMsg::GetUserReward {
+                    addr: Addr::unchecked(user),
+                },
+            )
+            .map(|res| res.rewards[0].amount.u128())
+            .unwrap_or_default()
+    }
+
+    fn staked(&self, user: &str) -> u128 {
+        self.app
+            .wrap()
+            .query_wasm_smart::<GetStakeResponse>(
+                &self.stake,
+                &QueryMsg::GetUserStakeInfo {
+                    addr: Addr::unchecked(user),
+                },
+            )
+            .map(|res| res.info.stake_amount.u128())
+            .unwrap_or_default()
+    }
+
+    fn total_staked(&self) -> u128 {
+        let state: GetStateResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(&self.stake, &QueryMsg::GetState {})
+            .unwrap();
+        state.total_staked.u128()
+    }
+
+    fn claimed(&self, user: &str) -> u128 {
+        let res: BalanceResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                &self.reward_token,
+                &Cw20QueryMsg::Balance {
+                    address: user.to_string(),
+                },
+            )
+            .unwrap();
+        res.balance.u128()
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn reward_math_invariants(
+        apr in 0..=100u64,
+        steps in prop::collection::vec((op(), 0..30 * 86_400u64), 1..40),
+    ) {
+        let mut suite = Suite::new(apr);
+        let mut apr = apr as u128;
+        let mut stakers: Vec<Staker> = USERS.iter().map(|_| Staker::default()).collect();
+        let mut pending: Vec<u128> = USERS.iter().map(|_| 0).collect();
+
+        for (op, elapsed) in steps {
+            suite.advance(elapsed);
+            for staker in stakers.iter_mut() {
+                staker.reward_integral += staker.staked * apr * elapsed as u128;
+            }
+
+            let mut claimer = None;
+            match op {
+                Op::Stake { user, amount } => {
+                    suite.stake(USERS[user], amount);
+                    stakers[user].staked += amount;
+                    stakers[user].settlements += 1;
+                }
+                Op::Unstake { user, amount } => {
+                    let amount = amount.min(stakers[user].staked);
+                    if amount > 0 {
+                        suite.execute(
+                            USERS[user],
+                            ExecuteMsg::StartUnstake { amount: Uint128::new(amount) },
+                        );
+                        stakers[user].staked -= amount;
+                        stakers[user].settlements += 1;
+                    }
+                }
+                Op::Claim { user } => {
+                    if stakers[user].settlements > 0 {
+                        suite.execute(
+                            USERS[user],
+                            ExecuteMsg::ClaimReward { amount: None, recipient: None },
+                        );
+                        stakers[user].settlements += 1;
+                        claimer = Some(user);
+                    }
+                }
+                Op::SetApr { percent } => {
+                    suite.execute(
+                        OPERATOR,
+                        ExecuteMsg::UpdateRewardStream {
+                            id: 0,
+                            rate: Some(Decimal::percent(percent)),
+                            end_time: None,
+                        },
+                    );
+                    apr = percent as u128;
+                }
+            }
+
+            // rewards never go down without a claim
+            for (user, addr) in USERS.iter().enumerate() {
+                let now = suite.pending(addr);
+                if claimer == Some(user) {
+                    prop_assert_eq!(now, 0);
+                } else {
+                    prop_assert!(now >= pending[user], "{} reward went down", addr);
+                }
+                pending[user] = now;
+            }
+
+            // the stakes add up to STAKED_TOTAL
+            let staked: Vec<u128> = USERS.iter().map(|addr| suite.staked(addr)).collect();
+            prop_assert_eq!(staked.iter().sum::<u128>(), suite.total_staked());
+            for (user, staker) in stakers.iter().enumerate() {
+                prop_assert_eq!(staked[user], staker.staked);
+            }
+        }
+
+        // paid plus pending is the APR integral, rounded down once per settlement
+        for (user, addr) in USERS.iter().enumerate() {
+            let earned = suite.claimed(addr) + suite.pending(addr);
+            let expected = stakers[user].reward_integral / (100 * SECONDS_PER_YEAR);
+            prop_assert!(earned <= expected, "{} earned {} > {}", addr, earned, expected);
+            prop_assert!(
+                expected - earned <= stakers[user].settlements + 1,
+                "{} earned {} < {}",
+                addr,
+                earned,
+                expected
+            );
+        }
+    }
+}
@@ -1,19 +1,135 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Decimal, Empty, Timestamp, Uint128};
+use cw20::Denom;
+use cw_storage_plus::{
+    Index, IndexList, IndexedMap, Item, Map, MultiIndex, SnapshotItem, SnapshotMap, Strategy,
+};
+use cw_utils::Duration;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RewardMode {
+    // every staked token earns the stream rate per year
+    Apr {},
+    // the stream rate is a fixed budget per second shared pro rata by stakers,
+    // each NotifyRewardAmount spreads its deposit over `duration` seconds
+    FixedEmission { duration: Uint128 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
     pub allowed_operators: Vec<Addr>,
-    pub unstaking_duration: Uint128,
-    pub apr: String,
+    pub unstaking_duration: Duration,
     pub bdog_ratio: Uint128,
     pub gdog_ratio: Uint128,
+    // when set the stored ratios are scaled by how far the ratios of these
+    // contracts grew from their genesis values, unscaled if a Config query fails
+    pub dog_burn_address: Option<Addr>,
+    pub gdog_address: Option<Addr>,
     pub token_address: Addr,
+    // native or token factory denom staked instead of the CW20 at token_address
+    #[serde(default)]
+    pub stake_denom: Option<String>,
     pub token_source: Addr,
-    pub reward_token_address: Addr,
+    pub reward_mode: RewardMode,
+    pub lockup_tiers: Vec<LockupTier>,
+    // InstantUnstake is disabled when not set
+    pub instant_unstake_penalty: Option<PenaltyConfig>,
+    // whether stakers can move their stake with TransferStake
+    #[serde(default)]
+    pub transfers_enabled: bool,
+    #[serde(default)]
+    pub stake_limits: StakeLimits,
+    // CW20 minted for liquid stakes, this contract has to be its minter
+    #[serde(default)]
+    pub receipt_token: Option<Addr>,
+    // stakes are blocked and stakers can only withdraw their principal
+    #[serde(default)]
+    pub emergency_mode: bool,
+    #[serde(default)]
+    pub stake_gate: StakeGate,
+}
+
+// Who can add stake, exits are open to everybody
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakeGate {
+    Open {},
+    // addresses operators added with UpdateAllowlist
+    Allowlist {},
+    // addresses the contract answers IsMember with true for, like dog-drop
+    Membership { contract: Addr },
+}
+
+impl Default for StakeGate {
+    fn default() -> Self {
+        StakeGate::Open {}
+    }
+}
+
+// Staking caps, unset limits are not enforced
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct StakeLimits {
+    // smallest amount a single stake can add
+    pub min_stake: Option<Uint128>,
+    pub max_stake_per_address: Option<Uint128>,
+    pub max_total_stake: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct PenaltyConfig {
+    // share of an instant unstake kept as penalty, in basis points
+    pub bps: u64,
+    pub destination: PenaltyDestination,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PenaltyDestination {
+    Burn {},
+    Treasury { address: Addr },
+    // paid to the remaining stakers through the staked token reward stream
+    Redistribute {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct LockupTier {
+    pub id: u64,
+    // lock term in seconds
+    pub duration: Uint128,
+    // reward weight multiplier of tokens locked in this tier
+    pub boost: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardStream {
+    pub id: u64,
+    pub token: Denom,
+    // reward tokens paid for each staked token per year in APR mode,
+    // reward tokens emitted per second in fixed emission mode
+    pub rate: Decimal,
+    pub start_time: Uint128,
+    pub end_time: Option<Uint128>,
+    // accumulated reward of one staked token since the stream started
+    pub reward_per_token: Decimal,
+    pub last_update_time: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StreamReward {
+    pub stream_id: u64,
+    pub pending: Uint128,
+    pub reward_per_token_paid: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockedPosition {
+    pub tier_id: u64,
+    pub amount: Uint128,
+    pub boost: Decimal,
+    pub unlock_time: Timestamp,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -21,17 +137,147 @@ pub struct StakeInfo {
     pub owner: Addr,
     pub stake_amount: Uint128,
     pub unstaking_amount: Uint128,
-    pub reward_amount: Uint128,
-    pub apr: String,
-    pub stake_start_time: Uint128,
-    pub reward_start_time: Uint128,
-    pub unstaking_start_time: Uint128,
-    pub reward_end_time: Uint128,
+    pub rewards: Vec<StreamReward>,
+    pub stake_start_time: Timestamp,
+    // last time rewards were settled
+    pub reward_start_time: Timestamp,
+    pub unstaking_start_time: Timestamp,
+    // block the last unstake started at, for unstaking durations in blocks
+    #[serde(default)]
+    pub unstaking_start_height: u64,
     pub unstaking_process: bool,
-    pub unstake_end_time: Uint128,
+    #[serde(default)]
+    pub auto_compound: bool,
+    // part of stake_amount locked in lockup tiers
+    #[serde(default)]
+    pub locked_positions: Vec<LockedPosition>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimKind {
+    Reward { stream_id: u64, token: Denom },
+    // reward paid in the staked token and restaked instead of sent
+    Compounded { stream_id: u64, token: Denom },
+    // principal paid back by ClaimUnstaked, InstantUnstake or EmergencyWithdraw
+    Unstaked {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClaimRecord {
+    pub kind: ClaimKind,
+    pub amount: Uint128,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StreamClaimed {
+    pub stream_id: u64,
+    pub amount: Uint128,
+}
+
+// Lifetime totals of a user or of the whole contract
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ClaimStats {
+    pub rewards_claimed: Vec<StreamClaimed>,
+    pub unstaked_claimed: Uint128,
+    // number of claim records, the next record id of a user
+    pub claims: u64,
+}
+
+// Epoch mode bookkeeping. Streams are only accrued up to the start of `epoch`,
+// a finished epoch is shared by the weight that was staked through all of it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct EpochState {
+    // start of epoch 0
+    pub genesis: Uint128,
+    // epoch length in seconds
+    pub length: Uint128,
+    pub epoch: u64,
+    // weight staked since the start of `epoch` and not reduced since
+    pub eligible_weight: Uint128,
+}
+
+impl EpochState {
+    pub fn epoch_at(&self, seconds: u128) -> u64 {
+        (Uint128::new(seconds).saturating_sub(self.genesis) / self.length).u128() as u64
+    }
+
+    pub fn epoch_start(&self, epoch: u64) -> Uint128 {
+        self.genesis + self.length * Uint128::from(epoch)
+    }
+}
+
+// Weight an address held through `epoch`, the last epoch its stake changed in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct EpochStake {
+    pub epoch: u64,
+    pub eligible_weight: Uint128,
 }
 
 pub const STATE: Item<State> = Item::new("state");
-pub const STAKE_LIST: Map<Addr, StakeInfo> = Map::new("stake_list");
-pub const STAKED_TOTAL: Item<Uint128> = Item::new("total_staked_amount");
-pub const REWARD_TOTAL: Item<Uint128> = Item::new("reward_total");
+pub const REWARD_STREAMS: Item<Vec<RewardStream>> = Item::new("reward_streams");
+// Funded reward tokens not yet paid out, keyed by denom or CW20 address
+pub const REWARD_RESERVES: Map<&str, Uint128> = Map::new("reward_reserves");
+pub struct StakeIndexes<'a> {
+    // stakers ordered by staked amount
+    pub amount: MultiIndex<'a, u128, StakeInfo, Addr>,
+}
+
+impl<'a> IndexList<StakeInfo> for StakeIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<StakeInfo>> + '_> {
+        let v: Vec<&dyn Index<StakeInfo>> = vec![&self.amount];
+        Box::new(v.into_iter())
+    }
+}
+
+pub fn stake_list<'a>() -> IndexedMap<'a, Addr, StakeInfo, StakeIndexes<'a>> {
+    let indexes = StakeIndexes {
+        amount: MultiIndex::new(
+            |_pk, info| info.stake_amount.u128(),
+            "stake_list",
+            "stake_list__amount",
+        ),
+    };
+    IndexedMap::new("stake_list", indexes)
+}
+// addresses with a non zero stake
+pub const STAKER_COUNT: Item<u64> = Item::new("staker_count");
+// sum of every unstaking amount
+pub const UNBONDING_TOTAL: Item<Uint128> = Item::new("unbonding_total");
+// staked amount of every address, checkpointed each block for voting power
+pub const STAKED_BALANCES: SnapshotMap<&Addr, Uint128> = SnapshotMap::new(
+    "staked_balances",
+    "staked_balances__checkpoints",
+    "staked_balances__changelog",
+    Strategy::EveryBlock,
+);
+pub const STAKED_TOTAL: SnapshotItem<Uint128> = SnapshotItem::new(
+    "total_staked_amount",
+    "total_staked_amount__checkpoints",
+    "total_staked_amount__changelog",
+    Strategy::EveryBlock,
+);
+// sum of every stake weight, shares fixed emission rewards
+pub const TOTAL_WEIGHT: Item<Uint128> = Item::new("total_reward_weight");
+// address a staker's rewards are paid to instead of the staker
+pub const REWARD_BENEFICIARIES: Map<&Addr, Addr> = Map::new("reward_beneficiaries");
+// addresses that can stake in allowlist gating
+pub const ALLOWLIST: Map<&Addr, Empty> = Map::new("allowlist");
+// contracts notified of every stake change
+pub const HOOKS: Item<Vec<Addr>> = Item::new("hooks");
+// receipt tokens in circulation, shares of the liquid pool position
+pub const RECEIPT_SUPPLY: Item<Uint128> = Item::new("receipt_supply");
+// the liquid pool position, earns rewards but has no voting power and is not listed
+pub const LIQUID_POOL: Item<StakeInfo> = Item::new("liquid_pool");
+pub const CLAIM_HISTORY: Map<(&Addr, u64), ClaimRecord> = Map::new("claim_history");
+pub const USER_STATS: Map<&Addr, ClaimStats> = Map::new("user_stats");
+pub const TOTAL_STATS: Item<ClaimStats> = Item::new("total_stats");
+// only set in epoch mode
+pub const EPOCH: Item<EpochState> = Item::new("epoch");
+// reward per token of every stream, by stream id, when an epoch started
+pub const EPOCH_CHECKPOINTS: Map<u64, Vec<Decimal>> = Map::new("epoch_checkpoints");
+pub const EPOCH_STAKES: Map<&Addr, EpochStake> = Map::new("epoch_stakes");
+// owners of locked positions by unlock time in seconds, released in that order
+pub const LOCK_EXPIRIES: Map<(u64, &Addr), Empty> = Map::new("lock_expiries");
@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(unused_imports, clippy::module_inception, clippy::needless_late_init)]
 mod tests {
     use cosmwasm_std::testing::mock_env;
     use cosmwasm_std::Uint128;
@@ -40,3 +41,2665 @@ mod tests {
         println!("output : {}", output);
     }
 }
+
+use cosmwasm_std::testing::{
+    mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+};
+use cosmwasm_std::{
+    coins, from_binary, from_slice, to_binary, Addr, Attribute, BankMsg, Binary, ContractResult,
+    CosmosMsg, Decimal, OwnedDeps, Reply, ReplyOn, SubMsgResponse, SubMsgResult, SystemError,
+    SystemResult, Uint128, WasmMsg, WasmQuery,
+};
+use cw2::set_contract_version;
+use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20ReceiveMsg, Denom};
+use cw_utils::{Duration, Expiration};
+
+use crate::contract::{execute, instantiate, migrate, query, reply};
+use crate::legacy;
+use crate::msg::{
+    ExecuteMsg, InstantiateMsg, IsMemberResponse, MembershipQueryMsg, MigrateMsg, ReceiveMsg,
+    RewardStreamMsg, StakeChangedExecuteMsg, StakeChangedHookMsg,
+};
+use crate::state::{
+    stake_list, ClaimKind, LockupTier, PenaltyConfig, PenaltyDestination, RewardMode, StakeGate,
+    StakeInfo, StakeLimits, REWARD_RESERVES, REWARD_STREAMS, STAKED_TOTAL, TOTAL_WEIGHT,
+};
+use crate::ContractError;
+
+const TOKEN: &str = "token";
+const STAKER: &str = "staker";
+
+fn default_instantiate() -> InstantiateMsg {
+    InstantiateMsg {
+        allowed_operators: vec![Addr::unchecked("operator")],
+        unstaking_duration: Duration::Time(100),
+        bdog_ratio: Uint128::new(1),
+        gdog_ratio: Uint128::new(1),
+        dog_burn_address: None,
+        gdog_address: None,
+        token_address: Addr::unchecked(TOKEN),
+        stake_denom: None,
+        token_source: Addr::unchecked("source"),
+        reward_mode: RewardMode::Apr {},
+        reward_streams: vec![RewardStreamMsg {
+            token: Denom::Cw20(Addr::unchecked("reward")),
+            rate: Decimal::percent(20),
+            start_time: None,
+            end_time: None,
+        }],
+        lockup_tiers: vec![LockupTier {
+            id: 1,
+            duration: Uint128::new(30 * 86400),
+            boost: Decimal::percent(200),
+        }],
+        instant_unstake_penalty: None,
+        transfers_enabled: false,
+        stake_limits: Default::default(),
+        receipt_token: None,
+        epoch_length: None,
+        stake_gate: StakeGate::Open {},
+    }
+}
+
+fn stake_msg(sender: &str, amount: u128) -> ExecuteMsg {
+    ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: sender.to_string(),
+        amount: Uint128::new(amount),
+        msg: to_binary(&ReceiveMsg::Stake {}).unwrap(),
+    })
+}
+
+fn fund_msg(amount: u128) -> ExecuteMsg {
+    ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: "operator".to_string(),
+        amount: Uint128::new(amount),
+        msg: to_binary(&ReceiveMsg::FundRewards {}).unwrap(),
+    })
+}
+
+#[test]
+fn cancel_unstake_restakes_tokens() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(600),
+        },
+    )
+    .unwrap();
+    assert_eq!(STAKED_TOTAL.load(&deps.storage).unwrap(), Uint128::new(400));
+
+    // cannot cancel more than is unbonding
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::CancelUnstake {
+            amount: Uint128::new(601),
+        },
+    )
+    .unwrap_err();
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::CancelUnstake {
+            amount: Uint128::new(200),
+        },
+    )
+    .unwrap();
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.stake_amount, Uint128::new(600));
+    assert_eq!(info.unstaking_amount, Uint128::new(400));
+    assert!(info.unstaking_process);
+
+    execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::CancelUnstake {
+            amount: Uint128::new(400),
+        },
+    )
+    .unwrap();
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.stake_amount, Uint128::new(1000));
+    assert!(!info.unstaking_process);
+    assert_eq!(
+        STAKED_TOTAL.load(&deps.storage).unwrap(),
+        Uint128::new(1000)
+    );
+}
+
+#[test]
+fn compound_restakes_pending_reward() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_streams[0].rate = Decimal::percent(100);
+    msg.reward_streams[0].token = Denom::Cw20(Addr::unchecked(TOKEN));
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+
+    env.block.time = env.block.time.plus_seconds(86400);
+    let pending = query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards[0]
+        .amount;
+    assert!(!pending.is_zero());
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::Compound {},
+    )
+    .unwrap();
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.stake_amount, Uint128::new(1000) + pending);
+    assert_eq!(info.rewards[0].pending, Uint128::zero());
+    assert_eq!(STAKED_TOTAL.load(&deps.storage).unwrap(), info.stake_amount);
+    // restaked rewards count as paid out
+    let history =
+        query::reward_history(deps.as_ref(), Addr::unchecked(STAKER), None, None).unwrap();
+    assert_eq!(
+        history.records[0].record.kind,
+        ClaimKind::Compounded {
+            stream_id: 0,
+            token: Denom::Cw20(Addr::unchecked(TOKEN)),
+        }
+    );
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.rewards_claimed[0].amount, pending);
+
+    // opted-in stakers compound on their next interaction
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::SetAutoCompound { enabled: true },
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(86400);
+    let pending = query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards[0]
+        .amount;
+    execute(
+        deps.as_mut(),
+        env,
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 10),
+    )
+    .unwrap();
+    let restaked = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(
+        restaked.stake_amount,
+        info.stake_amount + pending + Uint128::new(10)
+    );
+    assert_eq!(
+        STAKED_TOTAL.load(&deps.storage).unwrap(),
+        restaked.stake_amount
+    );
+}
+
+#[test]
+fn compound_requires_matching_tokens() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    let err = execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::SetAutoCompound { enabled: true },
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::CompoundNotSupported {}));
+}
+
+#[test]
+fn claim_pays_every_reward_stream() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_streams.push(RewardStreamMsg {
+        token: Denom::Native("ugdog".to_string()),
+        rate: Decimal::percent(10),
+        start_time: None,
+        end_time: Some(Uint128::new(
+            env.block.time.seconds() as u128 + 31_536_000 / 2,
+        )),
+    });
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &coins(1_000_000, "ugdog")),
+        ExecuteMsg::FundRewards {},
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1_000_000),
+    )
+    .unwrap();
+
+    // one year later: 20% on the first stream, half a year of 10% on the second
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    let rewards = query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards;
+    assert_eq!(rewards[0].amount, Uint128::new(200_000));
+    assert_eq!(rewards[1].amount, Uint128::new(50_000));
+
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[1].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: STAKER.to_string(),
+            amount: coins(50_000, "ugdog"),
+        })
+    );
+    let rewards = query::user_reward(deps.as_ref(), env, Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards;
+    assert!(rewards.iter().all(|r| r.amount.is_zero()));
+}
+
+#[test]
+fn claim_is_limited_by_reward_reserve() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+
+    // only operators fund, and only with stream tokens
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("other", &[]),
+        fund_msg(1000),
+    )
+    .unwrap_err();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        fund_msg(1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1_000_000),
+    )
+    .unwrap();
+
+    // 200_000 per year emitted against a 1000 reserve
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.reward_reserves[0].amount, Uint128::new(1000));
+    assert_eq!(
+        state.reward_reserves[0].runway_seconds,
+        Some(Uint128::new(157_680))
+    );
+
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    let rewards = query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards;
+    assert_eq!(rewards[0].amount, Uint128::new(199_000));
+    let state = query::state(deps.as_ref(), env).unwrap();
+    assert_eq!(state.reward_reserves[0].amount, Uint128::zero());
+}
+
+#[test]
+fn fixed_emission_is_shared_pro_rata() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_mode = RewardMode::FixedEmission {
+        duration: Uint128::new(100),
+    };
+    msg.reward_streams[0].rate = Decimal::zero();
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 100),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("other", 300),
+    )
+    .unwrap();
+
+    let notify = ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: "operator".to_string(),
+        amount: Uint128::new(1000),
+        msg: to_binary(&ReceiveMsg::NotifyRewardAmount { stream_id: 0 }).unwrap(),
+    });
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        notify.clone(),
+    )
+    .unwrap();
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.reward_streams[0].rate, Decimal::percent(1000));
+    assert_eq!(
+        state.reward_streams[0].end_time,
+        Some(Uint128::new(env.block.time.seconds() as u128 + 100))
+    );
+
+    // half way through, a refill spreads 500 left + 1000 new over a fresh period
+    env.block.time = env.block.time.plus_seconds(50);
+    execute(deps.as_mut(), env.clone(), mock_info("reward", &[]), notify).unwrap();
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.reward_streams[0].rate, Decimal::percent(1500));
+
+    // nothing is emitted past the period end
+    env.block.time = env.block.time.plus_seconds(200);
+    let reward = |addr: &str| {
+        query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(addr))
+            .unwrap()
+            .rewards[0]
+            .amount
+    };
+    assert_eq!(reward(STAKER), Uint128::new(500));
+    assert_eq!(reward("other"), Uint128::new(1500));
+}
+
+#[test]
+fn fixed_emission_handles_18_decimal_budgets() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_mode = RewardMode::FixedEmission {
+        duration: Uint128::new(1_000_000),
+    };
+    msg.reward_streams[0].rate = Decimal::zero();
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    let token = 1_000_000_000_000_000_000u128;
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, token),
+    )
+    .unwrap();
+
+    // 1M tokens over 1M seconds emit 10^18 atomics of 10^18 per second
+    let notify = ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: "operator".to_string(),
+        amount: Uint128::new(1_000_000 * token),
+        msg: to_binary(&ReceiveMsg::NotifyRewardAmount { stream_id: 0 }).unwrap(),
+    });
+    execute(deps.as_mut(), env.clone(), mock_info("reward", &[]), notify).unwrap();
+
+    env.block.time = env.block.time.plus_seconds(400);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("other", token),
+    )
+    .unwrap();
+    let rewards = query::user_reward(deps.as_ref(), env, Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards;
+    assert_eq!(rewards[0].amount, Uint128::new(400 * token));
+}
+
+#[test]
+fn locked_stake_is_boosted_until_unlock() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("other", 3_650_000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: STAKER.to_string(),
+            amount: Uint128::new(3_650_000),
+            msg: to_binary(&ReceiveMsg::StakeLocked { tier_id: 1 }).unwrap(),
+        }),
+    )
+    .unwrap();
+
+    // locked tokens cannot start unstaking before the term ends
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(1),
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::MoreThanUnlockedAmount { .. }));
+
+    // 60 days later the lock earned double for its 30 day term only
+    env.block.time = env.block.time.plus_seconds(60 * 86400);
+    let reward = |addr: &str| {
+        query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(addr))
+            .unwrap()
+            .rewards[0]
+            .amount
+    };
+    // each settled part is floored, allow a couple of units of rounding
+    assert!(Uint128::new(120_000) - reward("other") <= Uint128::new(2));
+    assert!(Uint128::new(180_000) - reward(STAKER) <= Uint128::new(2));
+
+    let positions =
+        query::user_positions(deps.as_ref(), env.clone(), Addr::unchecked(STAKER)).unwrap();
+    assert!(positions.positions[0].unlocked);
+    assert_eq!(positions.unlocked_amount, Uint128::new(3_650_000));
+
+    execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(3_650_000),
+        },
+    )
+    .unwrap();
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert!(info.locked_positions.is_empty());
+    assert_eq!(
+        STAKED_TOTAL.load(&deps.storage).unwrap(),
+        Uint128::new(3_650_000)
+    );
+}
+
+#[test]
+fn expired_boost_is_released_at_unlock_time() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_mode = RewardMode::FixedEmission {
+        duration: Uint128::new(90 * 86400),
+    };
+    msg.reward_streams[0].rate = Decimal::zero();
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("other", 1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: STAKER.to_string(),
+            amount: Uint128::new(1000),
+            msg: to_binary(&ReceiveMsg::StakeLocked { tier_id: 1 }).unwrap(),
+        }),
+    )
+    .unwrap();
+    // 3 tokens a second, 7_776_000 every 30 days
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "operator".to_string(),
+            amount: Uint128::new(3 * 90 * 86400),
+            msg: to_binary(&ReceiveMsg::NotifyRewardAmount { stream_id: 0 }).unwrap(),
+        }),
+    )
+    .unwrap();
+
+    // the lock ran out after 30 days and its boost is released then, without
+    // the staker coming back, so the second 30 days are split evenly
+    env.block.time = env.block.time.plus_seconds(60 * 86400);
+    let claim = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, addr: &str| {
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(addr, &[]),
+            ExecuteMsg::ClaimReward {
+                amount: None,
+                recipient: None,
+            },
+        )
+        .unwrap();
+        let amount = res
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "paid")
+            .unwrap()
+            .value
+            .parse::<u128>()
+            .unwrap();
+        Uint128::new(amount)
+    };
+    let other = claim(&mut deps, "other");
+    assert_eq!(
+        TOTAL_WEIGHT.load(&deps.storage).unwrap(),
+        Uint128::new(2000)
+    );
+    assert!(Uint128::new(6_480_000) - other <= Uint128::new(2));
+    let staker = claim(&mut deps, STAKER);
+    assert!(Uint128::new(9_072_000) - staker <= Uint128::new(2));
+
+    // no emission is left unpaid
+    assert!(Uint128::new(2 * 7_776_000) - (other + staker) <= Uint128::new(3));
+}
+
+#[test]
+fn adding_to_a_tier_keeps_earlier_unlock_times() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    let stake_locked = |amount: u128| {
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: STAKER.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&ReceiveMsg::StakeLocked { tier_id: 1 }).unwrap(),
+        })
+    };
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_locked(1000),
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(20 * 86400);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_locked(1),
+    )
+    .unwrap();
+
+    // the first deposit unlocks on its own term, the added token is still locked
+    env.block.time = env.block.time.plus_seconds(11 * 86400);
+    let positions =
+        query::user_positions(deps.as_ref(), env.clone(), Addr::unchecked(STAKER)).unwrap();
+    assert_eq!(positions.positions.len(), 2);
+    assert_eq!(positions.unlocked_amount, Uint128::new(1000));
+    execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(1000),
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn instant_unstake_redistributes_penalty() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_streams[0].token = Denom::Cw20(Addr::unchecked(TOKEN));
+    msg.reward_streams[0].rate = Decimal::zero();
+    msg.instant_unstake_penalty = Some(PenaltyConfig {
+        bps: 1000,
+        destination: PenaltyDestination::Redistribute {},
+    });
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("other", 1000),
+    )
+    .unwrap();
+
+    let simulated = query::simulate_instant_unstake(deps.as_ref(), Uint128::new(500)).unwrap();
+    assert_eq!(simulated.penalty, Uint128::new(50));
+    assert_eq!(simulated.payout, Uint128::new(450));
+
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::InstantUnstake {
+            amount: Uint128::new(500),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(
+        STAKED_TOTAL.load(&deps.storage).unwrap(),
+        Uint128::new(1500)
+    );
+
+    // the remaining 1500 staked share the penalty by weight
+    let reward = |addr: &str| {
+        query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(addr))
+            .unwrap()
+            .rewards[0]
+            .amount
+    };
+    assert_eq!(reward("other"), Uint128::new(33));
+    assert_eq!(reward(STAKER), Uint128::new(16));
+}
+
+#[test]
+fn stake_for_credits_recipient() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    let res = execute(
+        deps.as_mut(),
+        env,
+        mock_info(TOKEN, &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "dog-drop".to_string(),
+            amount: Uint128::new(500),
+            msg: to_binary(&ReceiveMsg::StakeFor {
+                recipient: STAKER.to_string(),
+            })
+            .unwrap(),
+        }),
+    )
+    .unwrap();
+    assert!(res
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "funder" && attr.value == "dog-drop"));
+    assert!(res
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "beneficiary" && attr.value == STAKER));
+
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.stake_amount, Uint128::new(500));
+    assert!(stake_list()
+        .may_load(&deps.storage, Addr::unchecked("dog-drop"))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn transfer_stake_settles_both_parties() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("other", 1000),
+    )
+    .unwrap();
+
+    let transfer = ExecuteMsg::TransferStake {
+        recipient: "other".to_string(),
+        amount: Uint128::new(400),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        transfer.clone(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::TransferStakeDisabled {}));
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        ExecuteMsg::EditState {
+            allowed_operators: None,
+            token_address: None,
+            unstaking_duration: None,
+            bdog_ratio: None,
+            gdog_ratio: None,
+            dog_burn_address: None,
+            gdog_address: None,
+            token_source: None,
+            lockup_tiers: None,
+            instant_unstake_penalty: None,
+            transfers_enabled: Some(true),
+            stake_limits: None,
+            receipt_token: None,
+            emergency_mode: None,
+            stake_gate: None,
+        },
+    )
+    .unwrap();
+
+    // half a year at 20% earns both stakers 100 before the transfer
+    env.block.time = env.block.time.plus_seconds(31_536_000 / 2);
+    execute(deps.as_mut(), env.clone(), mock_info(STAKER, &[]), transfer).unwrap();
+
+    let load = |addr: &str| {
+        stake_list()
+            .load(&deps.storage, Addr::unchecked(addr))
+            .unwrap()
+    };
+    assert_eq!(load(STAKER).stake_amount, Uint128::new(600));
+    assert_eq!(load("other").stake_amount, Uint128::new(1400));
+    assert_eq!(load(STAKER).rewards[0].pending, Uint128::new(100));
+    assert_eq!(load("other").rewards[0].pending, Uint128::new(100));
+    assert_eq!(
+        STAKED_TOTAL.load(&deps.storage).unwrap(),
+        Uint128::new(2000)
+    );
+
+    // the moved stake earns for the recipient from here on
+    env.block.time = env.block.time.plus_seconds(31_536_000 / 2);
+    let reward = |addr: &str| {
+        query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked(addr))
+            .unwrap()
+            .rewards[0]
+            .amount
+    };
+    assert_eq!(reward(STAKER), Uint128::new(160));
+    assert_eq!(reward("other"), Uint128::new(240));
+
+    let err = execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::TransferStake {
+            recipient: STAKER.to_string(),
+            amount: Uint128::new(1),
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::SelfTransfer {}));
+}
+
+#[test]
+fn partial_claim_keeps_the_rest_accrued() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1_000_000),
+    )
+    .unwrap();
+
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimReward {
+            amount: Some(Uint128::new(50_000)),
+            recipient: Some("cold-wallet".to_string()),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "reward".to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "cold-wallet".to_string(),
+                amount: Uint128::new(50_000),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+    let attr = |key: &str| {
+        res.attributes
+            .iter()
+            .find(|attr| attr.key == key)
+            .unwrap()
+            .value
+            .clone()
+    };
+    assert_eq!(attr("paid"), "50000");
+    assert_eq!(attr("remaining"), "150000");
+
+    let rewards = query::user_reward(deps.as_ref(), env, Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards;
+    assert_eq!(rewards[0].amount, Uint128::new(150_000));
+}
+
+#[test]
+fn multiplier_follows_live_ratios() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.bdog_ratio = Uint128::new(1);
+    msg.gdog_ratio = Uint128::new(2);
+    msg.dog_burn_address = Some(Addr::unchecked("dog-burn"));
+    msg.gdog_address = Some(Addr::unchecked("gdog"));
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+    // only gdog answers, its side grows from 1000 to 1500 bDOG per gDOG
+    deps.querier.update_wasm(|query| match query {
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == "gdog" => {
+            SystemResult::Ok(ContractResult::Ok(gdog_config(1500, 501)))
+        }
+        _ => SystemResult::Err(SystemError::NoSuchContract {
+            addr: "dog-burn".to_string(),
+        }),
+    });
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.multiplier, Decimal::percent(300));
+
+    // dog-burn moved from 10 to 20 bDOG per DOG, which halves the boost
+    deps.querier.update_wasm(|query| match query {
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == "gdog" => {
+            SystemResult::Ok(ContractResult::Ok(gdog_config(1500, 501)))
+        }
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == "dog-burn" => {
+            SystemResult::Ok(ContractResult::Ok(dog_burn_config(20)))
+        }
+        _ => SystemResult::Err(SystemError::NoSuchContract {
+            addr: "unknown".to_string(),
+        }),
+    });
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.multiplier, Decimal::percent(150));
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1_000_000),
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    let rewards = query::user_reward(deps.as_ref(), env, Addr::unchecked(STAKER))
+        .unwrap()
+        .rewards;
+    assert_eq!(rewards[0].amount, Uint128::new(300_000));
+}
+
+// Config responses as the dog-burn and gdog contracts send them
+fn dog_burn_config(ratio: u128) -> Binary {
+    Binary::from(
+        format!(
+            r#"{{"owner":"owner","dog_token_address":"dog","bdog_token_address":"bdog","dog_burn_amount":"10000000000000","bdog_sent_amount":"0","bdog_current_amount":"0","ratio":"{}"}}"#,
+            ratio
+        )
+        .as_bytes(),
+    )
+}
+
+fn gdog_config(ratio: u128, step: u128) -> Binary {
+    Binary::from(
+        format!(
+            r#"{{"owner":"owner","bdog_token_address":"bdog","gdog_token_address":"gdog","bdog_burn_amount":"0","gdog_sent_amount":"0","ratio":"{}","step":"{}"}}"#,
+            ratio, step
+        )
+        .as_bytes(),
+    )
+}
+
+#[test]
+fn stake_limits_are_enforced() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    let mut msg = default_instantiate();
+    msg.stake_limits = StakeLimits {
+        min_stake: Some(Uint128::new(100)),
+        max_stake_per_address: Some(Uint128::new(1000)),
+        max_total_stake: Some(Uint128::new(1500)),
+    };
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+    let mut stake = |sender: &str, amount: u128| {
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(TOKEN, &[]),
+            stake_msg(sender, amount),
+        )
+    };
+    let err = stake(STAKER, 99).unwrap_err();
+    assert!(matches!(err, ContractError::BelowMinStake { .. }));
+    stake(STAKER, 1000).unwrap();
+    let err = stake(STAKER, 100).unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::AddressStakeLimitExceeded { .. }
+    ));
+    let err = stake("other", 600).unwrap_err();
+    assert!(matches!(err, ContractError::TotalStakeLimitExceeded { .. }));
+    stake("other", 500).unwrap();
+
+    // raising the cap lets the launch grow
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        ExecuteMsg::EditState {
+            allowed_operators: None,
+            token_address: None,
+            unstaking_duration: None,
+            bdog_ratio: None,
+            gdog_ratio: None,
+            dog_burn_address: None,
+            gdog_address: None,
+            token_source: None,
+            lockup_tiers: None,
+            instant_unstake_penalty: None,
+            transfers_enabled: None,
+            stake_limits: Some(StakeLimits {
+                min_stake: Some(Uint128::new(100)),
+                max_stake_per_address: Some(Uint128::new(1000)),
+                max_total_stake: Some(Uint128::new(3000)),
+            }),
+            receipt_token: None,
+            emergency_mode: None,
+            stake_gate: None,
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("other", 500),
+    )
+    .unwrap();
+    let state = query::state(deps.as_ref(), env).unwrap();
+    assert_eq!(state.total_staked, Uint128::new(2000));
+    assert_eq!(state.stake_limits.max_total_stake, Some(Uint128::new(3000)));
+}
+
+#[test]
+fn transfers_and_cancels_respect_stake_caps() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    let mut msg = default_instantiate();
+    msg.transfers_enabled = true;
+    msg.stake_limits = StakeLimits {
+        min_stake: Some(Uint128::new(100)),
+        max_stake_per_address: Some(Uint128::new(1000)),
+        max_total_stake: Some(Uint128::new(1500)),
+    };
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    for (sender, amount) in [(STAKER, 1000), ("other", 500)] {
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(TOKEN, &[]),
+            stake_msg(sender, amount),
+        )
+        .unwrap();
+    }
+
+    // the recipient is capped, the min stake does not apply
+    let transfer = |amount: u128| ExecuteMsg::TransferStake {
+        recipient: "other".to_string(),
+        amount: Uint128::new(amount),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        transfer(600),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::AddressStakeLimitExceeded { .. }
+    ));
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        transfer(50),
+    )
+    .unwrap();
+
+    // cancelling an unstake can not overfill a pool others filled meanwhile
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(500),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("third", 450),
+    )
+    .unwrap();
+    let cancel = |amount: u128| ExecuteMsg::CancelUnstake {
+        amount: Uint128::new(amount),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        cancel(100),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::TotalStakeLimitExceeded { .. }));
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        cancel(50),
+    )
+    .unwrap();
+    let state = query::state(deps.as_ref(), env).unwrap();
+    assert_eq!(state.total_staked, Uint128::new(1500));
+}
+
+#[test]
+fn native_denom_stake_and_claim() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.stake_denom = Some("ustake".to_string());
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+    // the CW20 receive hook no longer stakes
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::InvalidToken {}));
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &coins(1000, "uother")),
+        ExecuteMsg::Stake {},
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::Payment(_)));
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &coins(1000, "ustake")),
+        ExecuteMsg::Stake {},
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(400),
+        },
+    )
+    .unwrap();
+
+    env.block.time = env.block.time.plus_seconds(100);
+    let res = execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimUnstaked {
+            amount: Uint128::new(400),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: STAKER.to_string(),
+            amount: coins(400, "ustake"),
+        })
+    );
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.stake_amount, Uint128::new(600));
+}
+
+#[test]
+fn liquid_receipts_track_compounded_pool() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_streams[0].token = Denom::Cw20(Addr::unchecked(TOKEN));
+    msg.receipt_token = Some(Addr::unchecked("sash"));
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+
+    let stake_liquid = |sender: &str, amount: u128| {
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: sender.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&ReceiveMsg::StakeLiquid {}).unwrap(),
+        })
+    };
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_liquid(STAKER, 1000),
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "sash".to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Mint {
+                recipient: STAKER.to_string(),
+                amount: Uint128::new(1000),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    // a year at 20% compounds the pool to 1200, so 1200 buys 1000 shares
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_liquid("other", 1200),
+    )
+    .unwrap();
+    assert!(res
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "shares" && attr.value == "1000"));
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.receipt_supply, Uint128::new(2000));
+    assert_eq!(state.total_staked, Uint128::new(2400));
+
+    // only the receipt token redeems shares
+    let unstake_liquid = ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: STAKER.to_string(),
+        amount: Uint128::new(1000),
+        msg: to_binary(&ReceiveMsg::UnstakeLiquid {}).unwrap(),
+    });
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        unstake_liquid.clone(),
+    )
+    .unwrap_err();
+    execute(deps.as_mut(), env, mock_info("sash", &[]), unstake_liquid).unwrap();
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.unstaking_amount, Uint128::new(1200));
+    assert!(info.unstaking_process);
+    let state = query::state(deps.as_ref(), mock_env()).unwrap();
+    assert_eq!(state.total_staked, Uint128::new(1200));
+}
+
+#[test]
+fn liquid_pool_has_no_voting_power_and_pays_other_streams() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_streams[0].token = Denom::Cw20(Addr::unchecked(TOKEN));
+    msg.receipt_token = Some(Addr::unchecked("sash"));
+    msg.stake_limits.max_stake_per_address = Some(Uint128::new(1500));
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    // the staker holds every receipt share minted below
+    deps.querier.update_wasm(|query| match query {
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == "sash" => {
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&BalanceResponse {
+                    balance: Uint128::new(1000),
+                })
+                .unwrap(),
+            ))
+        }
+        _ => SystemResult::Err(SystemError::NoSuchContract {
+            addr: "unknown".to_string(),
+        }),
+    });
+
+    let stake_liquid = |amount: u128| {
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: STAKER.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&ReceiveMsg::StakeLiquid {}).unwrap(),
+        })
+    };
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_liquid(1000),
+    )
+    .unwrap();
+    // the receipts already held count towards the per address cap
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_liquid(600),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::AddressStakeLimitExceeded { .. }
+    ));
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_liquid(500),
+    )
+    .unwrap();
+
+    // the pool earns but neither votes nor shows up as a staker
+    let power = query::total_power_at_height(deps.as_ref(), env.clone(), None).unwrap();
+    assert_eq!(power.power, Uint128::zero());
+    let pool = query::pool_info(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(pool.stakers, 0);
+    assert_eq!(pool.total_staked, Uint128::new(1500));
+    let top = query::top_stakers(deps.as_ref(), None, None).unwrap();
+    assert!(top.stake_list.is_empty());
+
+    // a stream in another token closes the pool to new shares, which would
+    // take part of what it earned before they were minted
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        ExecuteMsg::AddRewardStream {
+            token: Denom::Cw20(Addr::unchecked("reward")),
+            rate: Decimal::percent(10),
+            start_time: None,
+            end_time: None,
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "late".to_string(),
+            amount: Uint128::new(1200),
+            msg: to_binary(&ReceiveMsg::StakeLiquid {}).unwrap(),
+        }),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::LiquidStreamNotCompoundable {}));
+
+    // a year later the pool compounded 300, burning every share pays
+    // all of the 150 the other stream accrued to the pool
+    let unstake_liquid = ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: STAKER.to_string(),
+        amount: Uint128::new(1500),
+        msg: to_binary(&ReceiveMsg::UnstakeLiquid {}).unwrap(),
+    });
+    let res = execute(deps.as_mut(), env, mock_info("sash", &[]), unstake_liquid).unwrap();
+    assert!(res.messages.iter().any(|msg| msg.msg
+        == CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "reward".to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: STAKER.to_string(),
+                amount: Uint128::new(150),
+            })
+            .unwrap(),
+            funds: vec![],
+        })));
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.unstaking_amount, Uint128::new(1800));
+}
+
+#[test]
+fn stake_changes_notify_hooks() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+
+    let add_hook = ExecuteMsg::AddHook {
+        addr: "leaderboard".to_string(),
+    };
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        add_hook.clone(),
+    )
+    .unwrap_err();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        add_hook,
+    )
+    .unwrap();
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(400),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "leaderboard".to_string(),
+            msg: to_binary(&StakeChangedExecuteMsg::StakeChangeHook(
+                StakeChangedHookMsg {
+                    addr: Addr::unchecked(STAKER),
+                    old: Uint128::new(1000),
+                    new: Uint128::new(600),
+                }
+            ))
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    // claims leave the staked amount alone but are reported too
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(30 * 86400);
+    let claim_hook = |stake: u128| {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "leaderboard".to_string(),
+            msg: to_binary(&StakeChangedExecuteMsg::StakeChangeHook(
+                StakeChangedHookMsg {
+                    addr: Addr::unchecked(STAKER),
+                    old: Uint128::new(stake),
+                    new: Uint128::new(stake),
+                },
+            ))
+            .unwrap(),
+            funds: vec![],
+        })
+    };
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimUnstaked {
+            amount: Uint128::new(400),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(res.messages[1].msg, claim_hook(600));
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(res.messages[1].msg, claim_hook(600));
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        ExecuteMsg::RemoveHook {
+            addr: "leaderboard".to_string(),
+        },
+    )
+    .unwrap();
+    assert!(query::hooks(deps.as_ref()).unwrap().hooks.is_empty());
+    let res = execute(
+        deps.as_mut(),
+        env,
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    assert!(res.messages.is_empty());
+}
+
+#[test]
+fn emergency_withdraw_returns_principal() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(300),
+        },
+    )
+    .unwrap();
+
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::EmergencyWithdraw {},
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::NotInEmergencyMode {}));
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        ExecuteMsg::EditState {
+            allowed_operators: None,
+            token_address: None,
+            unstaking_duration: None,
+            bdog_ratio: None,
+            gdog_ratio: None,
+            dog_burn_address: None,
+            gdog_address: None,
+            token_source: None,
+            lockup_tiers: None,
+            instant_unstake_penalty: None,
+            transfers_enabled: None,
+            stake_limits: None,
+            receipt_token: None,
+            emergency_mode: Some(true),
+            stake_gate: None,
+        },
+    )
+    .unwrap();
+    assert!(
+        query::state(deps.as_ref(), env.clone())
+            .unwrap()
+            .emergency_mode
+    );
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::EmergencyMode {}));
+
+    env.block.time = env.block.time.plus_seconds(86400);
+    let res = execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::EmergencyWithdraw {},
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: TOKEN.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: STAKER.to_string(),
+                amount: Uint128::new(1000),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+    assert_eq!(STAKED_TOTAL.load(&deps.storage).unwrap(), Uint128::zero());
+    assert!(stake_list()
+        .may_load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn emergency_exits_skip_unbonding_and_survive_failures() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_streams[0].token = Denom::Cw20(Addr::unchecked(TOKEN));
+    msg.receipt_token = Some(Addr::unchecked("sash"));
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        ExecuteMsg::AddHook {
+            addr: "leaderboard".to_string(),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "holder".to_string(),
+            amount: Uint128::new(500),
+            msg: to_binary(&ReceiveMsg::StakeLiquid {}).unwrap(),
+        }),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        ExecuteMsg::EditState {
+            allowed_operators: None,
+            token_address: None,
+            unstaking_duration: None,
+            bdog_ratio: None,
+            gdog_ratio: None,
+            dog_burn_address: None,
+            gdog_address: None,
+            token_source: None,
+            lockup_tiers: None,
+            instant_unstake_penalty: None,
+            transfers_enabled: None,
+            stake_limits: None,
+            receipt_token: None,
+            emergency_mode: Some(true),
+            stake_gate: None,
+        },
+    )
+    .unwrap();
+
+    // the streams are accrued before the withdrawn weight leaves, the hook
+    // reports a failure back instead of reverting the exit
+    env.block.time = env.block.time.plus_seconds(86400);
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::EmergencyWithdraw {},
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(res.messages[0].reply_on, ReplyOn::Error);
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "leaderboard".to_string(),
+            msg: to_binary(&StakeChangedExecuteMsg::StakeChangeHook(
+                StakeChangedHookMsg {
+                    addr: Addr::unchecked(STAKER),
+                    old: Uint128::new(1000),
+                    new: Uint128::zero(),
+                }
+            ))
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+    let streams = REWARD_STREAMS.load(&deps.storage).unwrap();
+    assert_eq!(
+        streams[0].last_update_time,
+        Uint128::new(env.block.time.seconds() as u128)
+    );
+    let failed = Reply {
+        id: res.messages[0].id,
+        result: SubMsgResult::Err("leaderboard is down".to_string()),
+    };
+    reply(deps.as_mut(), env.clone(), failed).unwrap();
+    let unknown = Reply {
+        id: 42,
+        result: SubMsgResult::Ok(SubMsgResponse {
+            events: vec![],
+            data: None,
+        }),
+    };
+    let err = reply(deps.as_mut(), env.clone(), unknown).unwrap_err();
+    assert!(matches!(err, ContractError::UnknownReplyId { id: 42 }));
+
+    // a reward state that can't accrue any more doesn't block the exit either
+    let mut streams = REWARD_STREAMS.load(&deps.storage).unwrap();
+    streams[0].reward_per_token = Decimal::MAX;
+    REWARD_STREAMS.save(&mut deps.storage, &streams).unwrap();
+    env.block.time = env.block.time.plus_seconds(86400);
+
+    // receipt holders are paid the pool value of their shares right away
+    let res = execute(
+        deps.as_mut(),
+        env,
+        mock_info("sash", &[]),
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "holder".to_string(),
+            amount: Uint128::new(500),
+            msg: to_binary(&ReceiveMsg::UnstakeLiquid {}).unwrap(),
+        }),
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[1].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: TOKEN.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "holder".to_string(),
+                amount: Uint128::new(500),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+    assert!(stake_list()
+        .may_load(&deps.storage, Addr::unchecked("holder"))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn claims_are_recorded_in_history_and_stats() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1_000_000),
+    )
+    .unwrap();
+
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: None,
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(400_000),
+        },
+    )
+    .unwrap();
+    env.block.height += 20;
+    env.block.time = env.block.time.plus_seconds(100);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimUnstaked {
+            amount: Uint128::new(400_000),
+        },
+    )
+    .unwrap();
+
+    let history =
+        query::reward_history(deps.as_ref(), Addr::unchecked(STAKER), None, None).unwrap();
+    assert_eq!(history.records.len(), 2);
+    assert_eq!(
+        history.records[0].record.kind,
+        ClaimKind::Reward {
+            stream_id: 0,
+            token: Denom::Cw20(Addr::unchecked("reward")),
+        }
+    );
+    assert_eq!(history.records[0].record.amount, Uint128::new(200_000));
+    assert_eq!(history.records[1].record.kind, ClaimKind::Unstaked {});
+    assert_eq!(history.records[1].record.height, env.block.height);
+    assert_eq!(history.records[1].record.time, env.block.time);
+    let page =
+        query::reward_history(deps.as_ref(), Addr::unchecked(STAKER), Some(0), None).unwrap();
+    assert_eq!(page.records[0].id, 1);
+
+    let stats = query::stats(deps.as_ref(), None, None).unwrap();
+    assert_eq!(stats.total.rewards_claimed[0].amount, Uint128::new(200_000));
+    assert_eq!(stats.total.unstaked_claimed, Uint128::new(400_000));
+    assert_eq!(stats.users[0].addr, Addr::unchecked(STAKER));
+    assert_eq!(stats.users[0].stats.claims, 2);
+    let state = query::state(deps.as_ref(), env).unwrap();
+    assert_eq!(state.rewards_claimed, stats.total.rewards_claimed);
+}
+
+#[test]
+fn top_stakers_and_pool_info() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    for (staker, amount) in [
+        ("alice", 300),
+        ("bob", 1000),
+        ("carol", 500),
+        ("dave", 700),
+        ("erin", 200),
+    ] {
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(TOKEN, &[]),
+            stake_msg(staker, amount),
+        )
+        .unwrap();
+    }
+    for (staker, amount) in [("bob", 600), ("erin", 200)] {
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(staker, &[]),
+            ExecuteMsg::StartUnstake {
+                amount: Uint128::new(amount),
+            },
+        )
+        .unwrap();
+    }
+
+    let owners = |list: Vec<StakeInfo>| {
+        list.into_iter()
+            .map(|info| info.owner.to_string())
+            .collect::<Vec<_>>()
+    };
+    let page = query::top_stakers(deps.as_ref(), None, Some(2)).unwrap();
+    assert_eq!(owners(page.stake_list), vec!["dave", "carol"]);
+    // erin unstaked everything and is no longer listed
+    let page = query::top_stakers(deps.as_ref(), Some(Addr::unchecked("carol")), Some(3)).unwrap();
+    assert_eq!(owners(page.stake_list), vec!["bob", "alice"]);
+
+    let pool = query::pool_info(deps.as_ref(), env).unwrap();
+    assert_eq!(pool.stakers, 4);
+    assert_eq!(pool.total_staked, Uint128::new(1900));
+    assert_eq!(pool.total_unbonding, Uint128::new(800));
+    assert_eq!(pool.tvl, Uint128::new(2700));
+    assert_eq!(pool.stream_aprs[0].apr, Decimal::percent(20));
+}
+
+#[test]
+fn unbonding_in_blocks_reports_release_height() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.unstaking_duration = Duration::Height(10);
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(400),
+        },
+    )
+    .unwrap();
+    let release_height = env.block.height + 10;
+    assert_eq!(
+        res.attributes[1],
+        Attribute::new("release", format!("expiration height: {}", release_height))
+    );
+
+    // time alone does not release it
+    env.block.height += 9;
+    env.block.time = env.block.time.plus_seconds(86400);
+    let claim = ExecuteMsg::ClaimUnstaked {
+        amount: Uint128::new(400),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        claim.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Unstaked tokens are released at expiration height: {}",
+            release_height
+        )
+    );
+    assert!(matches!(
+        err,
+        ContractError::MinUnstakingTimeRequired {
+            release: Expiration::AtHeight(height),
+        } if height == release_height
+    ));
+
+    env.block.height += 1;
+    execute(deps.as_mut(), env, mock_info(STAKER, &[]), claim).unwrap();
+    let info = stake_list()
+        .load(&deps.storage, Addr::unchecked(STAKER))
+        .unwrap();
+    assert_eq!(info.unstaking_amount, Uint128::zero());
+    assert!(!info.unstaking_process);
+}
+
+#[test]
+fn allowlist_gates_stakes_but_not_exits() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.stake_gate = StakeGate::Allowlist {};
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::StakeNotAllowed {}));
+
+    let update = |add: &[&str], remove: &[&str]| ExecuteMsg::UpdateAllowlist {
+        add: add.iter().map(|addr| addr.to_string()).collect(),
+        remove: remove.iter().map(|addr| addr.to_string()).collect(),
+    };
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        update(&[STAKER], &[]),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::Unauthorized { .. }));
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        update(&[STAKER, "other"], &[]),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap();
+    assert!(
+        query::can_stake(deps.as_ref(), Addr::unchecked(STAKER))
+            .unwrap()
+            .can_stake
+    );
+    let allowlist = query::allowlist(deps.as_ref(), None, None).unwrap();
+    assert_eq!(
+        allowlist.addresses,
+        vec![Addr::unchecked("other"), Addr::unchecked(STAKER)]
+    );
+
+    // once removed the staker can only leave
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("operator", &[]),
+        update(&[], &[STAKER]),
+    )
+    .unwrap();
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1000),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::StakeNotAllowed {}));
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(1000),
+        },
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(100);
+    execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimUnstaked {
+            amount: Uint128::new(1000),
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn membership_contract_gates_stakes() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    let mut msg = default_instantiate();
+    msg.stake_gate = StakeGate::Membership {
+        contract: Addr::unchecked("dog-drop"),
+    };
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    deps.querier.update_wasm(|query| match query {
+        WasmQuery::Smart { contract_addr, msg } if contract_addr == "dog-drop" => {
+            let MembershipQueryMsg::IsMember { address } = from_binary(msg).unwrap();
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&IsMemberResponse {
+                    is_member: address == "alice",
+                })
+                .unwrap(),
+            ))
+        }
+        _ => SystemResult::Err(SystemError::NoSuchContract {
+            addr: "unknown".to_string(),
+        }),
+    });
+
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("alice", 1000),
+    )
+    .unwrap();
+    let err = execute(
+        deps.as_mut(),
+        env,
+        mock_info(TOKEN, &[]),
+        stake_msg("bob", 1000),
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::StakeNotAllowed {}));
+    assert!(
+        !query::can_stake(deps.as_ref(), Addr::unchecked("bob"))
+            .unwrap()
+            .can_stake
+    );
+}
+
+#[test]
+fn rewards_go_to_the_beneficiary() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    instantiate(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("creator", &[]),
+        default_instantiate(),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("reward", &[]),
+        fund_msg(1_000_000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg(STAKER, 1_000_000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::SetRewardBeneficiary {
+            addr: Some("treasury".to_string()),
+        },
+    )
+    .unwrap();
+    let beneficiary = |deps: &OwnedDeps<_, _, _>| {
+        query::reward_beneficiary(deps.as_ref(), Addr::unchecked(STAKER))
+            .unwrap()
+            .beneficiary
+    };
+    assert_eq!(beneficiary(&deps), Addr::unchecked("treasury"));
+
+    // a recipient in the claim can not redirect the beneficiary's rewards
+    env.block.time = env.block.time.plus_seconds(31_536_000);
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: Some("elsewhere".to_string()),
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(err, ContractError::RecipientWithBeneficiary {}));
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "reward".to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "treasury".to_string(),
+                amount: Uint128::new(200_000),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    // principal still returns to the staker
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(1_000_000),
+        },
+    )
+    .unwrap();
+    env.block.time = env.block.time.plus_seconds(100);
+    let res = execute(
+        deps.as_mut(),
+        env,
+        mock_info(STAKER, &[]),
+        ExecuteMsg::ClaimUnstaked {
+            amount: Uint128::new(1_000_000),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: TOKEN.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: STAKER.to_string(),
+                amount: Uint128::new(1_000_000),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(STAKER, &[]),
+        ExecuteMsg::SetRewardBeneficiary { addr: None },
+    )
+    .unwrap();
+    assert_eq!(beneficiary(&deps), Addr::unchecked(STAKER));
+}
+
+#[test]
+fn epoch_rewards_ignore_just_in_time_stakes() {
+    let mut deps = mock_dependencies();
+    let mut env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_mode = RewardMode::FixedEmission {
+        duration: Uint128::new(10_000),
+    };
+    msg.reward_streams[0].rate = Decimal::zero();
+    msg.epoch_length = Some(Uint128::new(1000));
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+    let genesis = env.block.time;
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("alice", 1000),
+    )
+    .unwrap();
+
+    // 1 token per second from the start of epoch 1
+    env.block.time = genesis.plus_seconds(1000);
+    let notify = ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: "operator".to_string(),
+        amount: Uint128::new(10_000),
+        msg: to_binary(&ReceiveMsg::NotifyRewardAmount { stream_id: 0 }).unwrap(),
+    });
+    execute(deps.as_mut(), env.clone(), mock_info("reward", &[]), notify).unwrap();
+
+    // bob stakes a second before epoch 1 ends and gets nothing of it
+    env.block.time = genesis.plus_seconds(1999);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("bob", 1000),
+    )
+    .unwrap();
+    let reward = |deps: &OwnedDeps<_, _, _>, time, addr: &str| {
+        let mut env = mock_env();
+        env.block.time = time;
+        query::user_reward(deps.as_ref(), env, Addr::unchecked(addr))
+            .unwrap()
+            .rewards[0]
+            .amount
+    };
+    let time = genesis.plus_seconds(2500);
+    assert_eq!(reward(&deps, time, "alice"), Uint128::new(1000));
+    assert_eq!(reward(&deps, time, "bob"), Uint128::zero());
+    let time = genesis.plus_seconds(3000);
+    assert_eq!(reward(&deps, time, "alice"), Uint128::new(1500));
+    assert_eq!(reward(&deps, time, "bob"), Uint128::new(500));
+
+    // unstaking half during epoch 3 leaves alice 500 earning for it
+    env.block.time = genesis.plus_seconds(3500);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("alice", &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(500),
+        },
+    )
+    .unwrap();
+    let epoch = query::epoch(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(epoch.epoch, 3);
+    assert_eq!(epoch.start, Uint128::new(genesis.seconds() as u128 + 3000));
+    assert_eq!(epoch.eligible_weight, Uint128::new(1500));
+
+    let time = genesis.plus_seconds(4000);
+    assert_eq!(reward(&deps, time, "alice"), Uint128::new(1833));
+    assert_eq!(reward(&deps, time, "bob"), Uint128::new(1166));
+
+    env.block.time = time;
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("alice", &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "reward".to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "alice".to_string(),
+                amount: Uint128::new(1833),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    // carol joins epoch 4 and tops up just before it ends, claiming her
+    // unbonded tokens after alice moved the contract on must not make the
+    // top up eligible
+    env.block.time = genesis.plus_seconds(4800);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("carol", 1000),
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("carol", &[]),
+        ExecuteMsg::StartUnstake {
+            amount: Uint128::new(500),
+        },
+    )
+    .unwrap();
+    env.block.time = genesis.plus_seconds(4999);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(TOKEN, &[]),
+        stake_msg("carol", 1_000_000),
+    )
+    .unwrap();
+    env.block.time = genesis.plus_seconds(5100);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("alice", &[]),
+        ExecuteMsg::ClaimReward {
+            amount: None,
+            recipient: None,
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("carol", &[]),
+        ExecuteMsg::ClaimUnstaked {
+            amount: Uint128::new(500),
+        },
+    )
+    .unwrap();
+    assert_eq!(reward(&deps, env.block.time, "carol"), Uint128::zero());
+    assert_eq!(reward(&deps, env.block.time, "bob"), Uint128::new(1833));
+}
+
+#[test]
+fn epoch_length_can_not_be_zero() {
+    let mut deps = mock_dependencies();
+    let mut msg = default_instantiate();
+    msg.epoch_length = Some(Uint128::zero());
+    let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidEpochLength {}));
+}
+
+#[test]
+fn emission_duration_can_not_be_zero() {
+    let mut deps = mock_dependencies();
+    let mut msg = default_instantiate();
+    msg.reward_mode = RewardMode::FixedEmission {
+        duration: Uint128::zero(),
+    };
+    let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+    assert!(matches!(err, ContractError::InvalidEmissionDuration {}));
+}
+
+#[test]
+fn fixed_emission_schedule_follows_the_budget() {
+    let mut deps = mock_dependencies();
+    let env = mock_env();
+    let mut msg = default_instantiate();
+    msg.reward_mode = RewardMode::FixedEmission {
+        duration: Uint128::new(86400),
+    };
+    msg.reward_streams[0].rate = Decimal::zero();
+    instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+    // neither the rate nor the end of a notified budget can be moved
+    let now = env.block.time.seconds() as u128;
+    for (rate, end_time) in [
+        (Some(Decimal::one()), None),
+        (None, Some(Uint128::new(now + 2 * 86400))),
+    ] {
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("operator", &[]),
+            ExecuteMsg::UpdateRewardStream {
+                id: 0,
+                rate,
+                end_time,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidRewardMode {}));
+    }
+    let streams = REWARD_STREAMS.load(&deps.storage).unwrap();
+    assert_eq!(streams[0].rate, Decimal::zero());
+    assert_eq!(streams[0].end_time, None);
+}
+
+// Stores the v0.1.0 fixtures the way that release left them on chain
+fn legacy_deps() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+    let mut deps = mock_dependencies();
+    let state: legacy::State = from_slice(include_bytes!("../testdata/v0_1_0_state.json")).unwrap();
+    let stake_list: Vec<legacy::StakeInfo> =
+        from_slice(include_bytes!("../testdata/v0_1_0_stake_list.json")).unwrap();
+    legacy::STATE.save(&mut deps.storage, &state).unwrap();
+    for stake_info in stake_list {
+        legacy::STAKE_LIST
+            .save(&mut deps.storage, stake_info.owner.clone(), &stake_info)
+            .unwrap();
+    }
+    set_contract_version(&mut deps.storage, "crates.io:cw20-stake", "0.1.0").unwrap();
+    deps.querier.update_wasm(|query| match query {
+        WasmQuery::Smart { contract_addr, .. } if contract_addr == "reward" => {
+            SystemResult::Ok(ContractResult::Ok(
+                to_binary(&BalanceResponse {
+                    balance: Uint128::new(1000),
+                })
+                .unwrap(),
+            ))
+        }
+        _ => SystemResult::Err(SystemError::NoSuchContract {
+            addr: "unknown".to_string(),
+        }),
+    });
+    deps
+}
+
+#[test]
+fn migrate_upgrades_v0_1_0_positions() {
+    let mut deps = legacy_deps();
+    let mut env = mock_env();
+    let res = migrate(deps.as_mut(), env.clone(), MigrateMsg {}).unwrap();
+    assert!(res
+        .attributes
+        .contains(&Attribute::new("from_version", "0.1.0")));
+
+    let state = query::state(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(state.multiplier, Decimal::percent(200));
+    let streams = REWARD_STREAMS.load(&deps.storage).unwrap();
+    assert_eq!(streams[0].token, Denom::Cw20(Addr::unchecked("reward")));
+    assert_eq!(streams[0].rate, Decimal::percent(20));
+
+    // 50 stored plus half a year of 1000 at 20% doubled by the multiplier
+    let alice = stake_list()
+        .load(&deps.storage, Addr::unchecked("alice"))
+        .unwrap();
+    assert_eq!(alice.rewards[0].pending, Uint128::new(250));
+    assert_eq!(alice.reward_start_time.seconds(), env.block.time.seconds());
+    let rewards = query::user_reward(deps.as_ref(), env.clone(), Addr::unchecked("alice"))
+        .unwrap()
+        .rewards;
+    assert_eq!(rewards[0].amount, Uint128::new(250));
+
+    let pool = query::pool_info(deps.as_ref(), env.clone()).unwrap();
+    assert_eq!(pool.stakers, 1);
+    assert_eq!(pool.total_staked, Uint128::new(1000));
+    assert_eq!(pool.total_unbonding, Uint128::new(500));
+    let top = query::top_stakers(deps.as_ref(), None, Some(1)).unwrap();
+    assert_eq!(top.stake_list[0].owner, Addr::unchecked("alice"));
+    assert_eq!(
+        REWARD_RESERVES.load(&deps.storage, "reward").unwrap(),
+        Uint128::new(1000)
+    );
+
+    // bob started unbonding 50 seconds before the migration
+    env.block.time = env.block.time.plus_seconds(50);
+    let res = execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("bob", &[]),
+        ExecuteMsg::ClaimUnstaked {
+            amount: Uint128::new(500),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: TOKEN.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "bob".to_string(),
+                amount: Uint128::new(500),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+
+    // running it again at the current version leaves the positions alone
+    migrate(deps.as_mut(), env, MigrateMsg {}).unwrap();
+    let again = stake_list()
+        .load(&deps.storage, Addr::unchecked("alice"))
+        .unwrap();
+    assert_eq!(again, alice);
+}
+
+#[test]
+fn migrate_owes_the_v0_1_0_floored_multiplier() {
+    let mut deps = legacy_deps();
+    let env = mock_env();
+    let mut state = legacy::STATE.load(&deps.storage).unwrap();
+    state.bdog_ratio = Uint128::new(2);
+    state.gdog_ratio = Uint128::new(3);
+    legacy::STATE.save(&mut deps.storage, &state).unwrap();
+    migrate(deps.as_mut(), env.clone(), MigrateMsg {}).unwrap();
+
+    // v0.1.0 boosted by 3 / 2 = 1, so 50 stored plus half a year of 1000 at 20%
+    let alice = stake_list()
+        .load(&deps.storage, Addr::unchecked("alice"))
+        .unwrap();
+    assert_eq!(alice.rewards[0].pending, Uint128::new(150));
+    let state = query::state(deps.as_ref(), env).unwrap();
+    assert_eq!(state.multiplier, Decimal::percent(150));
+}
+
+#[test]
+fn migrate_rejects_other_contracts_and_downgrades() {
+    let mut deps = legacy_deps();
+    set_contract_version(&mut deps.storage, "crates.io:cw20-base", "0.1.0").unwrap();
+    let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+    assert!(matches!(err, ContractError::CannotMigrate { .. }));
+
+    set_contract_version(&mut deps.storage, "crates.io:cw20-stake", "9.0.0").unwrap();
+    let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+    assert!(matches!(err, ContractError::CannotMigrateVersion { .. }));
+}